@@ -0,0 +1,52 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::time::Duration;
+
+use test::Bencher;
+use realm_lb::{Balance, HealthConfig, Token};
+use realm_lb::round_robin::RoundRobin;
+
+#[bench]
+fn rr_next(b: &mut Bencher) {
+    let rr = RoundRobin::new(&[1; 16]);
+    b.iter(|| rr.next(&()));
+}
+
+#[bench]
+fn rr_next_fast(b: &mut Bencher) {
+    let rr = RoundRobin::new(&[1; 16]).with_fast_window(Duration::from_micros(1));
+    b.iter(|| rr.next_fast(&()));
+}
+
+#[bench]
+fn rr_on_success_16(b: &mut Bencher) {
+    let rr = RoundRobin::with_health(&[1; 16], HealthConfig::default());
+    b.iter(|| rr.on_success(Token(15)));
+}
+
+#[bench]
+fn rr_on_success_255(b: &mut Bencher) {
+    let rr = RoundRobin::with_health(&[1; 255], HealthConfig::default());
+    b.iter(|| rr.on_success(Token(254)));
+}
+
+#[bench]
+fn rr_snapshot_into_255(b: &mut Bencher) {
+    let rr = RoundRobin::with_health(&[1; 255], HealthConfig::default());
+    let mut buf = Vec::new();
+    b.iter(|| rr.snapshot_into(&mut buf));
+}
+
+#[bench]
+fn rr_next_255(b: &mut Bencher) {
+    let rr = RoundRobin::new(&[1; 255]);
+    b.iter(|| rr.next(&()));
+}
+
+#[bench]
+fn rr_next_topk_255(b: &mut Bencher) {
+    let rr = RoundRobin::new(&[1; 255]);
+    b.iter(|| rr.next_topk(&(), 8));
+}
@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+
+use super::{Balance, Strategy, Token};
+
+/// Lower bound of the load factor, so that
+/// a fully loaded peer still sees a little traffic.
+const MIN_FACTOR: f64 = 0.01;
+
+/// Adaptive node.
+#[derive(Debug)]
+struct Node {
+    cw: f64,
+    weight: u8,
+    load: f32,
+    token: Token,
+}
+
+impl Node {
+    fn effective_weight(&self) -> f64 {
+        let factor = (1.0 - self.load as f64).max(MIN_FACTOR);
+        self.weight as f64 * factor
+    }
+}
+
+/// Server-informed balancer.
+///
+/// Smooth weighted round robin, where each weight is scaled
+/// down by the load a peer reports about itself.
+#[derive(Debug)]
+pub struct AdaptiveLoad {
+    nodes: Mutex<Vec<Node>>,
+    total: u8,
+}
+
+impl Balance for AdaptiveLoad {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::AdaptiveLoad.name()
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let nodes = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Node {
+                cw: 0.0,
+                weight: *w,
+                load: 0.0,
+                token: Token(i as u8),
+            })
+            .collect();
+
+        Self {
+            nodes: Mutex::new(nodes),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        // an empty pool, not a phantom peer
+        match self.total {
+            0 => return None,
+            1 => return Some(Token(0)),
+            _ => {}
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut tw = 0.0;
+        let mut best: Option<&mut Node> = None;
+        for p in nodes.iter_mut() {
+            let ew = p.effective_weight();
+            tw += ew;
+            p.cw += ew;
+
+            match best {
+                Some(ref x) if p.cw <= x.cw => {}
+                _ => best = Some(p),
+            }
+        }
+
+        best.map(|x| {
+            x.cw -= tw;
+            x.token
+        })
+    }
+
+    fn on_reported_load(&self, token: Token, load: f32) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.iter_mut().find(|n| n.token == token) {
+            p.load = if load.is_nan() { 0.0 } else { load.clamp(0.0, 1.0) };
+        }
+    }
+}
+
+impl AdaptiveLoad {
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| p.weight).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn al_reported_load() {
+        let al = AdaptiveLoad::new(&[1, 1, 1]);
+        let mut distro = [0f64; 3];
+
+        for _ in 0..3000 {
+            let token = al.next(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+        assert!(distro.iter().all(|x| *x == 1000.0));
+
+        // 1 - 0.5 = 0.5, 1 - 0.0 = 1.0, 1 - 0.75 = 0.25
+        al.on_reported_load(Token(0), 0.5);
+        al.on_reported_load(Token(2), 0.75);
+
+        let mut distro = [0f64; 3];
+        for _ in 0..7000 {
+            let token = al.next(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+
+        println!("{:?}", distro);
+        assert!((distro[0] - 2000.0).abs() <= 1.0);
+        assert!((distro[1] - 4000.0).abs() <= 1.0);
+        assert!((distro[2] - 1000.0).abs() <= 1.0);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{Balance, Token};
+use crate::error::BalanceError;
+use crate::round_robin::RoundRobin;
+use crate::snapshot::BalanceSnapshot;
+
+/// Management operation, e.g. received over a control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AdminCommand {
+    Disable(Token),
+    Enable(Token),
+    Drain(Token),
+    Undrain(Token),
+    SetWeight {
+        token: Token,
+        weight: u8,
+    },
+    /// Take a peer out of rotation for a while.
+    Quarantine {
+        token: Token,
+        secs: u32,
+    },
+    Snapshot,
+}
+
+/// Result of an [`AdminCommand`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AdminResponse {
+    Ok,
+    Snapshot(BalanceSnapshot),
+    Error(BalanceError),
+}
+
+/// Balancers driven by admin commands.
+pub trait Admin {
+    /// Run a command.
+    fn apply_admin(&self, cmd: AdminCommand) -> AdminResponse;
+}
+
+impl Admin for RoundRobin {
+    fn apply_admin(&self, cmd: AdminCommand) -> AdminResponse {
+        let token = match cmd {
+            AdminCommand::Disable(token)
+            | AdminCommand::Enable(token)
+            | AdminCommand::Drain(token)
+            | AdminCommand::Undrain(token)
+            | AdminCommand::SetWeight { token, .. }
+            | AdminCommand::Quarantine { token, .. } => Some(token),
+            AdminCommand::Snapshot => None,
+        };
+        if let Some(token) = token.filter(|x| x.0 >= self.total()) {
+            return AdminResponse::Error(BalanceError::UnknownToken(token));
+        }
+
+        log::info!("[lb]admin command: {:?}", cmd);
+        match cmd {
+            AdminCommand::Disable(token) => self.disable(token),
+            AdminCommand::Enable(token) => self.enable(token),
+            AdminCommand::Drain(token) => self.drain(token),
+            AdminCommand::Undrain(token) => self.undrain(token),
+            AdminCommand::SetWeight { token, weight } => self.set_weight(token, weight),
+            AdminCommand::Quarantine { token, secs } => self.report_unhealthy(token, secs),
+            AdminCommand::Snapshot => return AdminResponse::Snapshot(self.snapshot()),
+        }
+        AdminResponse::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::clock::MockClock;
+
+    fn distro(rr: &RoundRobin, n: usize) -> [u32; 3] {
+        let mut distro = [0; 3];
+        for _ in 0..n {
+            let token = rr.next(&()).unwrap();
+            distro[token.0 as usize] += 1;
+            rr.on_disconnect(token);
+        }
+        distro
+    }
+
+    #[test]
+    fn admin_commands() {
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::new(&[1, 1, 1]).with_clock(clock.clone());
+        let ok = |cmd| assert_eq!(rr.apply_admin(cmd), AdminResponse::Ok);
+
+        ok(AdminCommand::Disable(Token(0)));
+        assert!(!rr.is_enabled(Token(0)));
+        assert_eq!(distro(&rr, 30), [0, 15, 15]);
+        ok(AdminCommand::Enable(Token(0)));
+        assert!(rr.is_enabled(Token(0)));
+
+        ok(AdminCommand::Drain(Token(1)));
+        assert_eq!(distro(&rr, 30), [15, 0, 15]);
+        ok(AdminCommand::Undrain(Token(1)));
+        assert_eq!(distro(&rr, 30), [10, 10, 10]);
+
+        ok(AdminCommand::SetWeight {
+            token: Token(2),
+            weight: 4,
+        });
+        assert_eq!(rr.weights(), vec![1, 1, 4]);
+
+        ok(AdminCommand::Quarantine {
+            token: Token(2),
+            secs: 10,
+        });
+        assert!(!rr.is_healthy(Token(2)));
+        clock.advance(10);
+        assert!(rr.is_healthy(Token(2)));
+
+        match rr.apply_admin(AdminCommand::Snapshot) {
+            AdminResponse::Snapshot(x) => assert_eq!(x.nodes, rr.snapshot().nodes),
+            x => panic!("{:?}", x),
+        }
+
+        assert_eq!(
+            rr.apply_admin(AdminCommand::Drain(Token(3))),
+            AdminResponse::Error(BalanceError::UnknownToken(Token(3)))
+        );
+    }
+}
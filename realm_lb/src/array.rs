@@ -0,0 +1,112 @@
+use std::sync::Mutex;
+
+use super::{Balance, Token};
+
+/// Fixed-size round-robin node.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    cw: i32,
+    ew: u8,
+    weight: u8,
+    token: Token,
+}
+
+/// Round robin balancer over a fixed number of peers.
+///
+/// Same smooth weighted logic as [`RoundRobin`](crate::round_robin::RoundRobin),
+/// but the peers are stored inline, so it never touches the heap.
+#[derive(Debug)]
+pub struct ArrayBalancer<const N: usize> {
+    nodes: Mutex<[Node; N]>,
+}
+
+impl<const N: usize> Balance for ArrayBalancer<N> {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        N as u8
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        "array"
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(N <= u8::MAX as usize);
+        assert_eq!(weights.len(), N);
+
+        let mut nodes = [Node {
+            cw: 0,
+            ew: 0,
+            weight: 0,
+            token: Token(0),
+        }; N];
+
+        for (i, (node, w)) in nodes.iter_mut().zip(weights).enumerate() {
+            node.ew = *w;
+            node.weight = *w;
+            node.token = Token(i as u8);
+        }
+
+        Self {
+            nodes: Mutex::new(nodes),
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        if N == 0 {
+            return None;
+        }
+
+        if N == 1 {
+            return Some(Token(0));
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut tw: i32 = 0;
+        let mut best: Option<&mut Node> = None;
+        for p in nodes.iter_mut() {
+            tw = tw.saturating_add(p.ew as i32);
+            p.cw = p.cw.saturating_add(p.ew as i32);
+
+            if p.ew < p.weight {
+                p.ew += 1;
+            }
+
+            match best {
+                Some(ref x) if p.cw <= x.cw => {}
+                _ => best = Some(p),
+            }
+        }
+
+        best.map(|x| {
+            x.cw = x.cw.saturating_sub(tw);
+            x.token
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn ab_same_as_rr() {
+        fn run<const N: usize>(weights: [u8; N]) {
+            let ab = ArrayBalancer::<N>::new(&weights);
+            let rr = RoundRobin::new(&weights);
+
+            for _ in 0..10_000 {
+                assert_eq!(ab.next(&()), rr.next(&()));
+            }
+        }
+
+        run([]);
+        run([1]);
+        run([1, 1]);
+        run([5, 1, 1]);
+        run([1, 2, 3, 4]);
+        run([255, 1, 128, 7, 7, 3, 2, 1]);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::Token;
+use crate::health::EjectionReason;
+
+/// A change of a peer, or of the peer set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AuditEvent {
+    Ejected(EjectionReason),
+    Recovered,
+    /// Ejected too many times in a row, kept out longer.
+    Parked,
+    WeightChanged {
+        from: u8,
+        to: u8,
+    },
+    Disabled,
+    Enabled,
+    Drained,
+    Undrained,
+    /// All peers replaced, tokens now name the new ones.
+    TopologySwapped {
+        total: u8,
+    },
+}
+
+/// A recorded change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AuditEntry {
+    /// When it was made, in seconds.
+    pub at: u32,
+    /// None if it is not about a single peer.
+    pub token: Option<Token>,
+    pub event: AuditEvent,
+}
+
+/// Last changes, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    len: usize,
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Keep up to `len` changes, 0 disables it.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            entries: Mutex::new(VecDeque::with_capacity(len)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.len > 0
+    }
+
+    pub fn push(&self, at: u32, token: Option<Token>, event: AuditEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.len {
+            entries.pop_front();
+        }
+        entries.push_back(AuditEntry { at, token, event });
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit_bounded() {
+        let log = AuditLog::new(2);
+        log.push(1, Some(Token(0)), AuditEvent::Drained);
+        log.push(2, Some(Token(0)), AuditEvent::Undrained);
+        log.push(3, None, AuditEvent::TopologySwapped { total: 1 });
+        let at: Vec<u32> = log.entries().iter().map(|x| x.at).collect();
+        assert_eq!(at, vec![2, 3]);
+
+        let log = AuditLog::new(0);
+        log.push(1, Some(Token(0)), AuditEvent::Disabled);
+        assert!(log.entries().is_empty());
+    }
+}
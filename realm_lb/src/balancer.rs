@@ -2,16 +2,34 @@ use std::net::IpAddr;
 use std::sync::Arc;
 use std::fmt::{Display, Formatter};
 
-use crate::{Token, Balance};
+use crate::{Token, Balance, FailureClass};
+use crate::config::{BalanceConfig, BalanceOptions};
+use crate::error::BalanceError;
 use crate::ip_hash::IpHash;
 use crate::round_robin::RoundRobin;
+use crate::adaptive_load::AdaptiveLoad;
+use crate::capacity::Capacity;
+use crate::least_time::LeastTime;
+use crate::scored::ScoredBalancer;
+use crate::drr::Drr;
+use crate::min_deviation::MinDeviation;
 
 /// Balance strategy.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Strategy {
     Off,
     IpHash,
     RoundRobin,
+    #[cfg_attr(feature = "serde", serde(rename = "adaptive"))]
+    AdaptiveLoad,
+    Drr,
+    #[cfg_attr(feature = "serde", serde(rename = "mindev"))]
+    MinDeviation,
+    Capacity,
+    LeastTime,
+    Scored,
 }
 
 impl From<&str> for Strategy {
@@ -21,21 +39,40 @@ impl From<&str> for Strategy {
             "off" => Off,
             "iphash" => IpHash,
             "roundrobin" => RoundRobin,
+            "adaptive" => AdaptiveLoad,
+            "drr" => Drr,
+            "mindev" => MinDeviation,
+            "capacity" => Capacity,
+            "leasttime" => LeastTime,
+            "scored" => Scored,
             _ => panic!("unknown strategy: {}", s),
         }
     }
 }
 
-impl Display for Strategy {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl Strategy {
+    /// Name as configured, also reported by [`Balance::strategy_name`].
+    pub fn name(&self) -> &'static str {
         match self {
-            Strategy::Off => write!(f, "off"),
-            Strategy::IpHash => write!(f, "iphash"),
-            Strategy::RoundRobin => write!(f, "roundrobin"),
+            Strategy::Off => "off",
+            Strategy::IpHash => "iphash",
+            Strategy::RoundRobin => "roundrobin",
+            Strategy::AdaptiveLoad => "adaptive",
+            Strategy::Drr => "drr",
+            Strategy::MinDeviation => "mindev",
+            Strategy::Capacity => "capacity",
+            Strategy::LeastTime => "leasttime",
+            Strategy::Scored => "scored",
         }
     }
 }
 
+impl Display for Strategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 /// Balance context to select next peer.
 #[derive(Debug)]
 pub struct BalanceCtx<'a> {
@@ -48,6 +85,12 @@ pub enum Balancer {
     Off,
     IpHash(Arc<IpHash>),
     RoundRobin(Arc<RoundRobin>),
+    AdaptiveLoad(Arc<AdaptiveLoad>),
+    Drr(Arc<Drr>),
+    MinDeviation(Arc<MinDeviation>),
+    Capacity(Arc<Capacity>),
+    LeastTime(Arc<LeastTime>),
+    Scored(Arc<ScoredBalancer>),
 }
 
 impl Balancer {
@@ -57,7 +100,72 @@ impl Balancer {
             Strategy::Off => Self::Off,
             Strategy::IpHash => Self::IpHash(Arc::new(IpHash::new(weights))),
             Strategy::RoundRobin => Self::RoundRobin(Arc::new(RoundRobin::new(weights))),
+            Strategy::AdaptiveLoad => Self::AdaptiveLoad(Arc::new(AdaptiveLoad::new(weights))),
+            Strategy::Drr => Self::Drr(Arc::new(Drr::new(weights))),
+            Strategy::MinDeviation => Self::MinDeviation(Arc::new(MinDeviation::new(weights))),
+            Strategy::Capacity => Self::Capacity(Arc::new(Capacity::new(weights))),
+            Strategy::LeastTime => Self::LeastTime(Arc::new(LeastTime::new(weights))),
+            Strategy::Scored => Self::Scored(Arc::new(ScoredBalancer::new(weights))),
+        }
+    }
+
+    /// Constructor from a config, fail if the health check config is invalid,
+    /// or set for a strategy that does not support it.
+    pub fn from_config(config: &BalanceConfig) -> Result<Self, BalanceError> {
+        if config.strategy != Strategy::RoundRobin && config.options != BalanceOptions::default() {
+            return Err(BalanceError::InvalidConfig("options only apply to round robin"));
+        }
+
+        let balancer = match (config.strategy, config.health) {
+            (Strategy::RoundRobin, health) => {
+                let rr = match health {
+                    Some(health) => RoundRobin::try_with_health(&config.weights, health)?,
+                    None => RoundRobin::new(&config.weights),
+                };
+                Self::RoundRobin(Arc::new(rr.with_options(config.options)))
+            }
+            (Strategy::IpHash, Some(health)) => {
+                health.validate()?;
+                Self::IpHash(Arc::new(IpHash::new(&config.weights).with_health(health)))
+            }
+            (Strategy::Scored, Some(health)) => {
+                Self::Scored(Arc::new(ScoredBalancer::new(&config.weights).try_with_health(health)?))
+            }
+            (_, Some(_)) => {
+                return Err(BalanceError::InvalidConfig(
+                    "health check only applies to round robin, iphash and scored",
+                ))
+            }
+            (strategy, None) => Self::new(strategy, &config.weights),
+        };
+        Ok(balancer)
+    }
+
+    /// Get the config of this balancer, to build the same one again.
+    pub fn to_config(&self) -> BalanceConfig {
+        let weights = match self {
+            Balancer::Off => Vec::new(),
+            Balancer::IpHash(iphash) => iphash.weights(),
+            Balancer::RoundRobin(rr) => rr.weights(),
+            Balancer::AdaptiveLoad(al) => al.weights(),
+            Balancer::Drr(drr) => drr.weights(),
+            Balancer::MinDeviation(md) => md.weights(),
+            Balancer::Capacity(cap) => cap.weights(),
+            Balancer::LeastTime(lt) => lt.weights(),
+            Balancer::Scored(sb) => sb.weights(),
+        };
+
+        let mut config = BalanceConfig::new(self.strategy(), &weights);
+        match self {
+            Balancer::RoundRobin(rr) => {
+                config.health = rr.health_config();
+                config.options = rr.options();
+            }
+            Balancer::IpHash(iphash) => config.health = iphash.health_config(),
+            Balancer::Scored(sb) => config.health = sb.health_config(),
+            _ => {}
         }
+        config
     }
 
     /// Get current balance strategy.
@@ -66,6 +174,27 @@ impl Balancer {
             Balancer::Off => Strategy::Off,
             Balancer::IpHash(_) => Strategy::IpHash,
             Balancer::RoundRobin(_) => Strategy::RoundRobin,
+            Balancer::AdaptiveLoad(_) => Strategy::AdaptiveLoad,
+            Balancer::Drr(_) => Strategy::Drr,
+            Balancer::MinDeviation(_) => Strategy::MinDeviation,
+            Balancer::Capacity(_) => Strategy::Capacity,
+            Balancer::LeastTime(_) => Strategy::LeastTime,
+            Balancer::Scored(_) => Strategy::Scored,
+        }
+    }
+
+    /// Get the name of the strategy, "off" if there is none.
+    pub fn strategy_name(&self) -> &'static str {
+        match self {
+            Balancer::Off => Strategy::Off.name(),
+            Balancer::IpHash(iphash) => iphash.strategy_name(),
+            Balancer::RoundRobin(rr) => rr.strategy_name(),
+            Balancer::AdaptiveLoad(al) => al.strategy_name(),
+            Balancer::Drr(drr) => drr.strategy_name(),
+            Balancer::MinDeviation(md) => md.strategy_name(),
+            Balancer::Capacity(cap) => cap.strategy_name(),
+            Balancer::LeastTime(lt) => lt.strategy_name(),
+            Balancer::Scored(sb) => sb.strategy_name(),
         }
     }
 
@@ -75,6 +204,12 @@ impl Balancer {
             Balancer::Off => 0,
             Balancer::IpHash(iphash) => iphash.total(),
             Balancer::RoundRobin(rr) => rr.total(),
+            Balancer::AdaptiveLoad(al) => al.total(),
+            Balancer::Drr(drr) => drr.total(),
+            Balancer::MinDeviation(md) => md.total(),
+            Balancer::Capacity(cap) => cap.total(),
+            Balancer::LeastTime(lt) => lt.total(),
+            Balancer::Scored(sb) => sb.total(),
         }
     }
 
@@ -84,6 +219,132 @@ impl Balancer {
             Balancer::Off => Some(Token(0)),
             Balancer::IpHash(iphash) => iphash.next(ctx.src_ip),
             Balancer::RoundRobin(rr) => rr.next(&()),
+            Balancer::AdaptiveLoad(al) => al.next(&()),
+            Balancer::Drr(drr) => drr.next(&()),
+            Balancer::MinDeviation(md) => md.next(&()),
+            Balancer::Capacity(cap) => cap.next(&()),
+            Balancer::LeastTime(lt) => lt.next(&()),
+            Balancer::Scored(sb) => sb.next(&()),
+        }
+    }
+
+    /// Report a closed connection to a peer.
+    pub fn on_disconnect(&self, token: Token) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_disconnect(token),
+            Balancer::RoundRobin(rr) => rr.on_disconnect(token),
+            Balancer::AdaptiveLoad(al) => al.on_disconnect(token),
+            Balancer::Drr(drr) => drr.on_disconnect(token),
+            Balancer::MinDeviation(md) => md.on_disconnect(token),
+            Balancer::Capacity(cap) => cap.on_disconnect(token),
+            Balancer::LeastTime(lt) => lt.on_disconnect(token),
+            Balancer::Scored(sb) => sb.on_disconnect(token),
+        }
+    }
+
+    /// Report a successful connection to a peer.
+    pub fn on_success(&self, token: Token) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_success(token),
+            Balancer::RoundRobin(rr) => rr.on_success(token),
+            Balancer::AdaptiveLoad(al) => al.on_success(token),
+            Balancer::Drr(drr) => drr.on_success(token),
+            Balancer::MinDeviation(md) => md.on_success(token),
+            Balancer::Capacity(cap) => cap.on_success(token),
+            Balancer::LeastTime(lt) => lt.on_success(token),
+            Balancer::Scored(sb) => sb.on_success(token),
+        }
+    }
+
+    /// Report a failed connection to a peer.
+    pub fn on_failure(&self, token: Token) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_failure(token),
+            Balancer::RoundRobin(rr) => rr.on_failure(token),
+            Balancer::AdaptiveLoad(al) => al.on_failure(token),
+            Balancer::Drr(drr) => drr.on_failure(token),
+            Balancer::MinDeviation(md) => md.on_failure(token),
+            Balancer::Capacity(cap) => cap.on_failure(token),
+            Balancer::LeastTime(lt) => lt.on_failure(token),
+            Balancer::Scored(sb) => sb.on_failure(token),
+        }
+    }
+
+    /// Report a failed connection to a peer, with its cause.
+    pub fn on_failure_classified(&self, token: Token, class: FailureClass) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_failure_classified(token, class),
+            Balancer::RoundRobin(rr) => rr.on_failure_classified(token, class),
+            Balancer::AdaptiveLoad(al) => al.on_failure_classified(token, class),
+            Balancer::Drr(drr) => drr.on_failure_classified(token, class),
+            Balancer::MinDeviation(md) => md.on_failure_classified(token, class),
+            Balancer::Capacity(cap) => cap.on_failure_classified(token, class),
+            Balancer::LeastTime(lt) => lt.on_failure_classified(token, class),
+            Balancer::Scored(sb) => sb.on_failure_classified(token, class),
+        }
+    }
+
+    /// Report the load a peer advertises about itself.
+    pub fn on_reported_load(&self, token: Token, load: f32) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_reported_load(token, load),
+            Balancer::RoundRobin(rr) => rr.on_reported_load(token, load),
+            Balancer::AdaptiveLoad(al) => al.on_reported_load(token, load),
+            Balancer::Drr(drr) => drr.on_reported_load(token, load),
+            Balancer::MinDeviation(md) => md.on_reported_load(token, load),
+            Balancer::Capacity(cap) => cap.on_reported_load(token, load),
+            Balancer::LeastTime(lt) => lt.on_reported_load(token, load),
+            Balancer::Scored(sb) => sb.on_reported_load(token, load),
+        }
+    }
+
+    /// Report the free capacity a peer advertises about itself.
+    pub fn on_reported_capacity(&self, token: Token, free: f32) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_reported_capacity(token, free),
+            Balancer::RoundRobin(rr) => rr.on_reported_capacity(token, free),
+            Balancer::AdaptiveLoad(al) => al.on_reported_capacity(token, free),
+            Balancer::Drr(drr) => drr.on_reported_capacity(token, free),
+            Balancer::MinDeviation(md) => md.on_reported_capacity(token, free),
+            Balancer::Capacity(cap) => cap.on_reported_capacity(token, free),
+            Balancer::LeastTime(lt) => lt.on_reported_capacity(token, free),
+            Balancer::Scored(sb) => sb.on_reported_capacity(token, free),
+        }
+    }
+
+    /// Report the response time of a peer.
+    pub fn on_latency(&self, token: Token, micros: u64) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_latency(token, micros),
+            Balancer::RoundRobin(rr) => rr.on_latency(token, micros),
+            Balancer::AdaptiveLoad(al) => al.on_latency(token, micros),
+            Balancer::Drr(drr) => drr.on_latency(token, micros),
+            Balancer::MinDeviation(md) => md.on_latency(token, micros),
+            Balancer::Capacity(cap) => cap.on_latency(token, micros),
+            Balancer::LeastTime(lt) => lt.on_latency(token, micros),
+            Balancer::Scored(sb) => sb.on_latency(token, micros),
+        }
+    }
+
+    /// Report the time to establish a connection to a peer.
+    pub fn on_connect_time(&self, token: Token, micros: u64) {
+        match self {
+            Balancer::Off => {}
+            Balancer::IpHash(iphash) => iphash.on_connect_time(token, micros),
+            Balancer::RoundRobin(rr) => rr.on_connect_time(token, micros),
+            Balancer::AdaptiveLoad(al) => al.on_connect_time(token, micros),
+            Balancer::Drr(drr) => drr.on_connect_time(token, micros),
+            Balancer::MinDeviation(md) => md.on_connect_time(token, micros),
+            Balancer::Capacity(cap) => cap.on_connect_time(token, micros),
+            Balancer::LeastTime(lt) => lt.on_connect_time(token, micros),
+            Balancer::Scored(sb) => sb.on_connect_time(token, micros),
         }
     }
 
@@ -140,5 +401,188 @@ mod tests {
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
+        run(Strategy::AdaptiveLoad, &[]);
+        run(Strategy::AdaptiveLoad, &[1, 2, 3]);
+        run(Strategy::Drr, &[]);
+        run(Strategy::MinDeviation, &[]);
+        run(Strategy::Drr, &[1, 2, 3]);
+        run(Strategy::MinDeviation, &[1, 2, 3]);
+        run(Strategy::Capacity, &[]);
+        run(Strategy::Capacity, &[1, 2, 3]);
+        run(Strategy::LeastTime, &[]);
+        run(Strategy::LeastTime, &[1, 2, 3]);
+        run(Strategy::Scored, &[]);
+        run(Strategy::Scored, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn config_round_trip() {
+        use crate::{BalanceOptions, DrainPolicy, FailureAction, FailurePolicy, HealthConfig, RoundingPolicy};
+
+        for strategy in [
+            Strategy::Off,
+            Strategy::IpHash,
+            Strategy::RoundRobin,
+            Strategy::AdaptiveLoad,
+            Strategy::Drr,
+            Strategy::MinDeviation,
+            Strategy::Capacity,
+            Strategy::LeastTime,
+            Strategy::Scored,
+        ] {
+            let weights: &[u8] = if strategy == Strategy::Off { &[] } else { &[1, 2, 3] };
+            let config = BalanceConfig::new(strategy, weights);
+            assert_eq!(Balancer::from_config(&config).unwrap().to_config(), config);
+        }
+
+        let config = BalanceConfig {
+            strategy: Strategy::RoundRobin,
+            weights: vec![5, 0, 1],
+            health: Some(HealthConfig {
+                max_fails: 3,
+                failure_policy: FailurePolicy::default().with(FailureClass::Timeout, FailureAction::Eject),
+                ..Default::default()
+            }),
+            options: BalanceOptions {
+                snapshot_interval_secs: 2,
+                selection_history: 16,
+                drain_policy: DrainPolicy::LastResort,
+                rounding: RoundingPolicy::Ceil,
+                start_offset: 3,
+                stagger_seed: Some(7),
+                max_cycle_length: 1000,
+            },
+        };
+        let balancer = Balancer::from_config(&config).unwrap();
+        assert_eq!(balancer.to_config(), config);
+
+        // the configured weights, not the ones fitted to the cycle
+        let fitted = BalanceConfig {
+            weights: vec![200, 101, 50],
+            options: BalanceOptions {
+                max_cycle_length: 10,
+                ..config.options
+            },
+            ..config.clone()
+        };
+        assert_eq!(Balancer::from_config(&fitted).unwrap().to_config(), fitted);
+
+        let iphash = BalanceConfig {
+            strategy: Strategy::IpHash,
+            options: BalanceOptions::default(),
+            ..config.clone()
+        };
+        assert_eq!(Balancer::from_config(&iphash).unwrap().to_config(), iphash);
+        let scored = BalanceConfig {
+            strategy: Strategy::Scored,
+            ..iphash.clone()
+        };
+        assert_eq!(Balancer::from_config(&scored).unwrap().to_config(), scored);
+
+        // not silently dropped
+        let drr = BalanceConfig {
+            strategy: Strategy::Drr,
+            ..iphash.clone()
+        };
+        assert!(matches!(
+            Balancer::from_config(&drr),
+            Err(BalanceError::InvalidConfig(_))
+        ));
+        let options = BalanceConfig {
+            health: None,
+            ..config.clone()
+        };
+        for strategy in [Strategy::IpHash, Strategy::Drr] {
+            let config = BalanceConfig {
+                strategy,
+                ..options.clone()
+            };
+            assert!(matches!(
+                Balancer::from_config(&config),
+                Err(BalanceError::InvalidConfig(_))
+            ));
+        }
+
+        let mut invalid = config.clone();
+        invalid.health = Some(HealthConfig {
+            max_fails: 0,
+            ..Default::default()
+        });
+        assert!(matches!(
+            Balancer::from_config(&invalid),
+            Err(BalanceError::InvalidConfig(_))
+        ));
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&balancer.to_config()).unwrap();
+            let parsed: BalanceConfig = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, config);
+
+            let parsed: BalanceConfig = serde_json::from_str(r#"{"strategy": "mindev", "weights": [1, 2]}"#).unwrap();
+            assert_eq!(parsed, BalanceConfig::new(Strategy::MinDeviation, &[1, 2]));
+        }
+    }
+
+    #[test]
+    fn empty_pools() {
+        let ip = "1.1.1.1".parse::<IpAddr>().unwrap();
+        for strategy in [
+            Strategy::IpHash,
+            Strategy::RoundRobin,
+            Strategy::AdaptiveLoad,
+            Strategy::Drr,
+            Strategy::MinDeviation,
+            Strategy::Capacity,
+            Strategy::LeastTime,
+            Strategy::Scored,
+        ] {
+            let balancer = Balancer::new(strategy, &[]);
+            assert_eq!(balancer.next(BalanceCtx { src_ip: &ip }), None, "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn strategy_names() {
+        use crate::array::ArrayBalancer;
+        use crate::limit::GlobalLimit;
+
+        let names = [
+            (Strategy::Off, "off"),
+            (Strategy::IpHash, "iphash"),
+            (Strategy::RoundRobin, "roundrobin"),
+            (Strategy::AdaptiveLoad, "adaptive"),
+            (Strategy::Drr, "drr"),
+            (Strategy::MinDeviation, "mindev"),
+            (Strategy::Capacity, "capacity"),
+            (Strategy::LeastTime, "leasttime"),
+            (Strategy::Scored, "scored"),
+        ];
+        for (strategy, name) in names {
+            // one spelling, as configured, logged and parsed
+            assert_eq!(Balancer::new(strategy, &[1, 2]).strategy_name(), name);
+            assert_eq!(strategy.to_string(), name);
+            assert_eq!(Strategy::from(name), strategy);
+        }
+
+        assert_eq!(ArrayBalancer::<2>::new(&[1, 2]).strategy_name(), "array");
+        let limited: GlobalLimit<ScoredBalancer> = GlobalLimit::new(&[1, 2]);
+        assert_eq!(limited.strategy_name(), "scored");
+
+        // implemented outside of this crate
+        struct First;
+        impl Balance for First {
+            type State = ();
+            fn new(_: &[u8]) -> Self {
+                First
+            }
+            fn next(&self, _: &()) -> Option<Token> {
+                Some(Token(0))
+            }
+            fn total(&self) -> u8 {
+                1
+            }
+        }
+        assert_eq!(First::new(&[1]).strategy_name(), "custom");
     }
 }
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering};
+
+use crate::trickle::spread_turn;
+use crate::Token;
+
+// u8::MAX + 1 means none
+const NONE: u16 = u8::MAX as u16 + 1;
+
+/// A peer that receives a fixed fraction of all selections.
+#[derive(Debug)]
+pub(crate) struct Canary {
+    token: AtomicU16,
+    // f32 bits
+    fraction: AtomicU32,
+    count: AtomicU64,
+}
+
+impl Default for Canary {
+    fn default() -> Self {
+        Self {
+            token: AtomicU16::new(NONE),
+            fraction: AtomicU32::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Canary {
+    pub fn set(&self, token: Token, fraction: f32) {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        self.fraction.store(fraction.to_bits(), Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+        self.token.store(token.0 as u16, Ordering::Relaxed);
+    }
+
+    /// An independent copy, at the same turn.
+    pub fn fork(&self) -> Self {
+        Self {
+            token: AtomicU16::new(self.token.load(Ordering::Relaxed)),
+            fraction: AtomicU32::new(self.fraction.load(Ordering::Relaxed)),
+            count: AtomicU64::new(self.count.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.token.store(NONE, Ordering::Relaxed);
+    }
+
+    pub fn token(&self) -> Option<Token> {
+        match self.token.load(Ordering::Relaxed) {
+            NONE => None,
+            x => Some(Token(x as u8)),
+        }
+    }
+
+    /// Whether this selection goes to the canary, see [`spread_turn`].
+    pub fn turn(&self) -> bool {
+        spread_turn(&self.count, f32::from_bits(self.fraction.load(Ordering::Relaxed)))
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Mutex;
+
+use super::{Balance, Strategy, Token};
+
+/// Capacity node.
+#[derive(Debug)]
+struct Node {
+    cw: f64,
+    weight: u8,
+    free: f32,
+    token: Token,
+}
+
+impl Node {
+    fn effective_weight(&self) -> f64 {
+        self.weight as f64 * self.free as f64
+    }
+}
+
+/// Capacity-aware balancer.
+///
+/// Smooth weighted round robin, where each weight is scaled
+/// by the fraction of free capacity a peer reports about itself.
+/// Peers that never reported are assumed to be empty.
+#[derive(Debug)]
+pub struct Capacity {
+    nodes: Mutex<Vec<Node>>,
+    total: u8,
+}
+
+impl Balance for Capacity {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::Capacity.name()
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let nodes = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Node {
+                cw: 0.0,
+                weight: *w,
+                free: 1.0,
+                token: Token(i as u8),
+            })
+            .collect();
+
+        Self {
+            nodes: Mutex::new(nodes),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        // an empty pool, not a phantom peer
+        match self.total {
+            0 => return None,
+            1 => return Some(Token(0)),
+            _ => {}
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut tw = 0.0;
+        let mut best: Option<&mut Node> = None;
+        for p in nodes.iter_mut() {
+            let ew = p.effective_weight();
+            if ew <= 0.0 {
+                continue;
+            }
+            tw += ew;
+            p.cw += ew;
+
+            match best {
+                Some(ref x) if p.cw <= x.cw => {}
+                _ => best = Some(p),
+            }
+        }
+
+        // every peer is full
+        best.map(|x| {
+            x.cw -= tw;
+            x.token
+        })
+    }
+
+    fn on_reported_capacity(&self, token: Token, free: f32) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.free = if free.is_nan() { 1.0 } else { free.clamp(0.0, 1.0) };
+        }
+    }
+}
+
+impl Capacity {
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| p.weight).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_near_full_yields() {
+        let cap = Capacity::new(&[10, 2]);
+        let mut distro = [0f64; 2];
+        for _ in 0..1200 {
+            let token = cap.next(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+        assert_eq!(distro, [1000.0, 200.0]);
+
+        // 10 * 0.1 = 1.0, 2 * 1.0 = 2.0
+        cap.on_reported_capacity(Token(0), 0.1);
+        cap.on_reported_capacity(Token(1), 1.0);
+
+        let mut distro = [0f64; 2];
+        for _ in 0..3000 {
+            let token = cap.next(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+
+        println!("{:?}", distro);
+        assert!(distro[1] > distro[0]);
+        assert!((distro[0] - 1000.0).abs() <= 1.0);
+        assert!((distro[1] - 2000.0).abs() <= 1.0);
+
+        // full peers take nothing
+        cap.on_reported_capacity(Token(0), 0.0);
+        assert!((0..100).all(|_| cap.next(&()) == Some(Token(1))));
+        cap.on_reported_capacity(Token(1), 0.0);
+        assert_eq!(cap.next(&()), None);
+    }
+}
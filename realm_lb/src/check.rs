@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::{Balance, Token};
+use crate::round_robin::RoundRobin;
+
+/// Active health check config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveHealthCheck {
+    /// Max probes in flight at the same time.
+    pub max_concurrent_probes: usize,
+}
+
+impl Default for ActiveHealthCheck {
+    fn default() -> Self {
+        Self {
+            max_concurrent_probes: 8,
+        }
+    }
+}
+
+impl ActiveHealthCheck {
+    /// Probe peers whose ejection has timed out, report
+    /// the results back to the balancer.
+    ///
+    /// `probe` blocks until the peer answers, returns whether it is healthy.
+    pub fn recover<F>(&self, rr: &RoundRobin, probe: F)
+    where
+        F: Fn(Token) -> bool + Sync,
+    {
+        self.run(rr, &rr.half_open(), probe);
+    }
+
+    /// Probe all peers, report the results back to the balancer.
+    ///
+    /// At most `max_concurrent_probes` probes run at a time,
+    /// so a large pool is not hit all at once.
+    pub fn check_all<F>(&self, rr: &RoundRobin, probe: F)
+    where
+        F: Fn(Token) -> bool + Sync,
+    {
+        let tokens: Vec<Token> = rr.snapshot().nodes.iter().map(|x| x.token).collect();
+        self.run(rr, &tokens, probe);
+    }
+
+    fn run<F>(&self, rr: &RoundRobin, tokens: &[Token], probe: F)
+    where
+        F: Fn(Token) -> bool + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let workers = self.max_concurrent_probes.max(1).min(tokens.len());
+
+        // each worker runs one probe at a time
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| {
+                    while let Some(token) = tokens.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if probe(*token) {
+                            rr.on_success(*token);
+                        } else {
+                            rr.on_probe_failure(*token);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::clock::MockClock;
+    use crate::health::{EjectionReason, HealthConfig, NodeState};
+
+    #[test]
+    fn check_recover_concurrency() {
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1; 32], HealthConfig::default()).with_clock(clock.clone());
+        for i in 0..32 {
+            rr.on_failure(Token(i));
+        }
+        assert!(rr.half_open().is_empty());
+
+        clock.advance(11);
+        assert_eq!(rr.half_open().len(), 32);
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let probed = AtomicUsize::new(0);
+
+        let check = ActiveHealthCheck {
+            max_concurrent_probes: 4,
+        };
+        check.recover(&rr, |_| {
+            let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(2));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            probed.fetch_add(1, Ordering::SeqCst);
+            true
+        });
+
+        assert_eq!(probed.load(Ordering::SeqCst), 32);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 4);
+        assert!(rr.half_open().is_empty());
+        assert!(rr.snapshot().nodes.iter().all(|x| x.state == NodeState::Healthy));
+    }
+
+    #[test]
+    fn check_all_concurrency() {
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1; 255], HealthConfig::default()).with_clock(clock);
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let probed = AtomicUsize::new(0);
+
+        let check = ActiveHealthCheck {
+            max_concurrent_probes: 3,
+        };
+        check.check_all(&rr, |token| {
+            let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(n, Ordering::SeqCst);
+            thread::sleep(Duration::from_micros(200));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            probed.fetch_add(1, Ordering::SeqCst);
+            token.0 % 2 == 0
+        });
+
+        assert_eq!(probed.load(Ordering::SeqCst), 255);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 3);
+        assert_eq!(rr.healthy_count(), 128);
+        assert_eq!(rr.ejection_reason(Token(1)), Some(EjectionReason::ProbeFailed));
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Time source, in seconds.
+pub trait Clock: Debug + Send + Sync {
+    /// Current time.
+    fn now(&self) -> u32;
+}
+
+/// Wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u32 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32)
+    }
+}
+
+/// Manually driven clock, useful in tests.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicU32,
+}
+
+impl MockClock {
+    /// Constructor.
+    pub fn new(now: u32) -> Self {
+        Self {
+            now: AtomicU32::new(now),
+        }
+    }
+
+    /// Set current time.
+    pub fn set(&self, now: u32) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    /// Move time forward.
+    pub fn advance(&self, secs: u32) {
+        self.now.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u32 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
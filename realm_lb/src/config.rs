@@ -0,0 +1,51 @@
+use crate::Strategy;
+use crate::health::HealthConfig;
+use crate::round_robin::{DrainPolicy, RoundingPolicy};
+
+/// Round-robin options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct BalanceOptions {
+    /// See [`RoundRobin::with_snapshot_interval`](crate::round_robin::RoundRobin::with_snapshot_interval).
+    pub snapshot_interval_secs: u32,
+    /// See [`RoundRobin::with_selection_history`](crate::round_robin::RoundRobin::with_selection_history).
+    pub selection_history: usize,
+    /// See [`RoundRobin::with_drain_policy`](crate::round_robin::RoundRobin::with_drain_policy).
+    pub drain_policy: DrainPolicy,
+    /// See [`RoundRobin::with_rounding`](crate::round_robin::RoundRobin::with_rounding).
+    pub rounding: RoundingPolicy,
+    /// See [`RoundRobin::with_start_offset`](crate::round_robin::RoundRobin::with_start_offset).
+    pub start_offset: u8,
+    /// See [`RoundRobin::with_stagger`](crate::round_robin::RoundRobin::with_stagger).
+    pub stagger_seed: Option<u64>,
+    /// See [`RoundRobin::with_max_cycle_length`](crate::round_robin::RoundRobin::with_max_cycle_length).
+    pub max_cycle_length: u32,
+}
+
+/// Everything to build a balancer from.
+///
+/// Health check applies to round robin, iphash and scored, options
+/// to round robin only, other strategies reject them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalanceConfig {
+    pub strategy: Strategy,
+    pub weights: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub health: Option<HealthConfig>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub options: BalanceOptions,
+}
+
+impl BalanceConfig {
+    /// Config without health check or options.
+    pub fn new(strategy: Strategy, weights: &[u8]) -> Self {
+        Self {
+            strategy,
+            weights: weights.to_vec(),
+            health: None,
+            options: BalanceOptions::default(),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+
+use super::{Balance, Strategy, Token};
+
+/// Deficit round-robin node.
+#[derive(Debug)]
+struct Node {
+    deficit: u32,
+    weight: u8,
+    token: Token,
+}
+
+#[derive(Debug)]
+struct State {
+    nodes: Vec<Node>,
+    current: usize,
+}
+
+/// Deficit round robin balancer.
+///
+/// Peers are visited in order, each visit tops up the deficit of a peer
+/// by its weight, and every selection consumes one from the deficit.
+/// A peer is selected for a whole burst before moving on.
+#[derive(Debug)]
+pub struct Drr {
+    state: Mutex<State>,
+    total: u8,
+}
+
+impl Balance for Drr {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::Drr.name()
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let nodes = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Node {
+                deficit: 0,
+                weight: *w,
+                token: Token(i as u8),
+            })
+            .collect();
+
+        Self {
+            state: Mutex::new(State { nodes, current: 0 }),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        // an empty pool, not a phantom peer
+        match self.total {
+            0 => return None,
+            1 => return Some(Token(0)),
+            _ => {}
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.nodes.iter().all(|x| x.weight == 0) {
+            return None;
+        }
+
+        let State { nodes, current } = &mut *state;
+        while nodes[*current].deficit == 0 {
+            *current = (*current + 1) % nodes.len();
+            let p = &mut nodes[*current];
+            p.deficit = p.weight as u32;
+        }
+
+        let p = &mut nodes[*current];
+        p.deficit -= 1;
+        Some(p.token)
+    }
+}
+
+impl Drr {
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        let state = self.state.lock().unwrap();
+        state.nodes.iter().map(|p| p.weight).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn drr_skewed_weights() {
+        let weights = [1, 10, 100, 255];
+        let total_weight: f64 = weights.iter().map(|x| *x as f64).sum();
+        let drr = Drr::new(&weights);
+        let rr = RoundRobin::new(&weights);
+
+        let mut drr_distro = [0f64; 4];
+        let mut rr_distro = [0f64; 4];
+        for _ in 0..100_000 {
+            drr_distro[drr.next(&()).unwrap().0 as usize] += 1.0;
+            rr_distro[rr.next(&()).unwrap().0 as usize] += 1.0;
+        }
+
+        println!("drr: {:?}", drr_distro);
+        println!("rr: {:?}", rr_distro);
+        for (i, w) in weights.iter().enumerate() {
+            let expected = *w as f64 / total_weight;
+            assert!((drr_distro[i] / 100_000.0 - expected).abs() < 1e-3);
+            assert!((rr_distro[i] / 100_000.0 - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn drr_bursts() {
+        let drr = Drr::new(&[3, 1, 0, 2]);
+        let seq: Vec<u8> = (0..12).map(|_| drr.next(&()).unwrap().0).collect();
+        assert_eq!(seq, vec![1, 3, 3, 0, 0, 0, 1, 3, 3, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt::{Display, Formatter};
+
+use crate::Token;
+
+/// Balancer errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum BalanceError {
+    /// All weights are zero.
+    ZeroWeights,
+    /// Fewer peers are available than wanted.
+    NotEnoughPeers { wanted: usize, available: usize },
+    /// All peers are draining.
+    Draining,
+    /// The global concurrency cap is reached.
+    GlobalSaturated,
+    /// A config value makes no sense.
+    InvalidConfig(&'static str),
+    /// The token is not of a current peer.
+    UnknownToken(Token),
+}
+
+impl Display for BalanceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalanceError::ZeroWeights => write!(f, "all weights are zero"),
+            BalanceError::NotEnoughPeers { wanted, available } => {
+                write!(f, "want {} peers, only {} available", wanted, available)
+            }
+            BalanceError::Draining => write!(f, "all peers are draining"),
+            BalanceError::GlobalSaturated => write!(f, "global concurrency cap reached"),
+            BalanceError::InvalidConfig(reason) => write!(f, "invalid config: {}", reason),
+            BalanceError::UnknownToken(token) => write!(f, "unknown peer token: {}", token.0),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+/// A wait gave up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out")
+    }
+}
+
+impl std::error::Error for Timeout {}
@@ -0,0 +1,151 @@
+/// Cause of a failed request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FailureClass {
+    ConnectRefused,
+    TlsHandshake,
+    Timeout,
+    Status5xx,
+    Dns,
+}
+
+impl FailureClass {
+    const COUNT: usize = 5;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What a failure does to the health of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FailureAction {
+    /// Not counted.
+    Ignore,
+    /// Counted toward `max_fails` with the given weight.
+    Count(u32),
+    /// Eject the peer at once.
+    Eject,
+}
+
+/// Action of each failure class.
+///
+/// Serialized as a table keyed by class, a missing class
+/// keeps its default action, e.g.
+/// `{ "timeout": { "count": 2 }, "status_5xx": { "count": 3 } }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "PolicyTable", into = "PolicyTable"))]
+pub struct FailurePolicy {
+    actions: [FailureAction; FailureClass::COUNT],
+}
+
+impl Default for FailurePolicy {
+    /// Every class counts once, except DNS failures,
+    /// which are a resolver problem rather than a peer problem.
+    fn default() -> Self {
+        let mut policy = Self {
+            actions: [FailureAction::Count(1); FailureClass::COUNT],
+        };
+        policy.set(FailureClass::Dns, FailureAction::Ignore);
+        policy
+    }
+}
+
+impl FailurePolicy {
+    /// Get the action of a class.
+    pub fn get(&self, class: FailureClass) -> FailureAction {
+        self.actions[class.index()]
+    }
+
+    /// Set the action of a class.
+    pub fn set(&mut self, class: FailureClass, action: FailureAction) {
+        self.actions[class.index()] = action;
+    }
+
+    /// Builder-style [`set`](Self::set).
+    pub fn with(mut self, class: FailureClass, action: FailureAction) -> Self {
+        self.set(class, action);
+        self
+    }
+}
+
+/// Serialized form of a policy.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct PolicyTable {
+    connect_refused: FailureAction,
+    tls_handshake: FailureAction,
+    timeout: FailureAction,
+    status_5xx: FailureAction,
+    dns: FailureAction,
+}
+
+#[cfg(feature = "serde")]
+impl Default for PolicyTable {
+    fn default() -> Self {
+        FailurePolicy::default().into()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<PolicyTable> for FailurePolicy {
+    fn from(table: PolicyTable) -> Self {
+        FailurePolicy::default()
+            .with(FailureClass::ConnectRefused, table.connect_refused)
+            .with(FailureClass::TlsHandshake, table.tls_handshake)
+            .with(FailureClass::Timeout, table.timeout)
+            .with(FailureClass::Status5xx, table.status_5xx)
+            .with(FailureClass::Dns, table.dns)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<FailurePolicy> for PolicyTable {
+    fn from(policy: FailurePolicy) -> Self {
+        PolicyTable {
+            connect_refused: policy.get(FailureClass::ConnectRefused),
+            tls_handshake: policy.get(FailureClass::TlsHandshake),
+            timeout: policy.get(FailureClass::Timeout),
+            status_5xx: policy.get(FailureClass::Status5xx),
+            dns: policy.get(FailureClass::Dns),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{Balance, HealthConfig, Token};
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn failure_policy_table() {
+        let json = r#"{
+            "max_fails": 4,
+            "failure_policy": {
+                "timeout": { "count": 2 },
+                "connect_refused": { "count": 3 }
+            }
+        }"#;
+        let health: HealthConfig = serde_json::from_str(json).unwrap();
+        let policy = health.failure_policy;
+        assert_eq!(policy.get(FailureClass::Timeout), FailureAction::Count(2));
+        assert_eq!(policy.get(FailureClass::ConnectRefused), FailureAction::Count(3));
+        assert_eq!(policy.get(FailureClass::TlsHandshake), FailureAction::Count(1));
+        assert_eq!(policy.get(FailureClass::Dns), FailureAction::Ignore);
+
+        let rr = RoundRobin::with_health(&[1, 1], health);
+        rr.on_failure_classified(Token(0), FailureClass::Timeout);
+        assert_eq!(rr.snapshot().nodes[0].fails, 2);
+        rr.on_failure_classified(Token(1), FailureClass::ConnectRefused);
+        assert_eq!(rr.snapshot().nodes[1].fails, 3);
+
+        let back: FailurePolicy = serde_json::from_str(&serde_json::to_string(&policy).unwrap()).unwrap();
+        assert_eq!(back, policy);
+    }
+}
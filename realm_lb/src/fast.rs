@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Token;
+
+const EMPTY: u64 = u64::MAX;
+
+/// Last selection, reused by bursts within a short window.
+///
+/// The timestamp (micros) and the token are packed into one word,
+/// so that a lookup is a single atomic load.
+#[derive(Debug)]
+pub(crate) struct FastPath {
+    window: u64,
+    base: Instant,
+    last: AtomicU64,
+}
+
+impl FastPath {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.as_micros() as u64,
+            base: Instant::now(),
+            last: AtomicU64::new(EMPTY),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.window != 0
+    }
+
+    fn now(&self) -> u64 {
+        self.base.elapsed().as_micros() as u64
+    }
+
+    /// Get the cached token if it is still fresh.
+    pub fn get(&self) -> Option<Token> {
+        let last = self.last.load(Ordering::Relaxed);
+        if last == EMPTY || self.now().saturating_sub(last >> 8) >= self.window {
+            return None;
+        }
+        Some(Token(last as u8))
+    }
+
+    pub fn put(&self, token: Token) {
+        self.last.store((self.now() << 8) | token.0 as u64, Ordering::Relaxed);
+    }
+
+    pub fn invalidate(&self) {
+        self.last.store(EMPTY, Ordering::Relaxed);
+    }
+}
+
+impl Default for FastPath {
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
@@ -0,0 +1,324 @@
+use crate::error::BalanceError;
+use crate::failure::{FailureClass, FailurePolicy};
+
+/// Passive health check config.
+///
+/// A peer is ejected after `max_fails` failures, and
+/// is kept out of rotation for `fail_timeout_secs`.
+///
+/// For `recovery_grace_secs` after a peer recovers,
+/// failures only count at a reduced rate.
+///
+/// Classified failures are counted as `failure_policy` says.
+///
+/// Each success takes `success_credit` off the failure count,
+/// 0 clears it at once. A peer with a long failure history then
+/// needs several successes before it stops being one failure
+/// away from ejection.
+///
+/// With `weight_scaled_timeout`, a peer of weight N is kept out
+/// for `fail_timeout_secs / N`, so that heavy peers, which leave
+/// a larger hole in the pool, are probed back sooner.
+///
+/// A peer ejected `max_consecutive_ejections` times in a row, without
+/// staying up for `fail_timeout_secs` in between, is parked and kept out
+/// for `parked_secs` instead, until it stays up. 0 never parks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct HealthConfig {
+    pub max_fails: u32,
+    pub fail_timeout_secs: u32,
+    pub recovery_grace_secs: u32,
+    pub failure_policy: FailurePolicy,
+    pub success_credit: u32,
+    pub weight_scaled_timeout: bool,
+    pub max_consecutive_ejections: u32,
+    pub parked_secs: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            recovery_grace_secs: 0,
+            failure_policy: FailurePolicy::default(),
+            success_credit: 0,
+            weight_scaled_timeout: false,
+            max_consecutive_ejections: 0,
+            parked_secs: 600,
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Reject values that would break routing.
+    ///
+    /// A zero `max_fails` ejects every peer before it ever fails,
+    /// a zero `fail_timeout_secs` never keeps a peer out.
+    pub fn validate(&self) -> Result<(), BalanceError> {
+        if self.max_fails == 0 {
+            return Err(BalanceError::InvalidConfig("max_fails must be greater than 0"));
+        }
+        if self.fail_timeout_secs == 0 {
+            return Err(BalanceError::InvalidConfig("fail_timeout_secs must be greater than 0"));
+        }
+        Ok(())
+    }
+
+    /// Get how long a peer of `weight` is kept out of rotation.
+    pub fn fail_timeout(&self, weight: u8) -> u32 {
+        if !self.weight_scaled_timeout || weight <= 1 {
+            return self.fail_timeout_secs;
+        }
+        self.fail_timeout_secs.div_ceil(weight as u32)
+    }
+
+    /// Config of a peer of `weight`.
+    pub(crate) fn for_weight(&self, weight: u8) -> Self {
+        Self {
+            fail_timeout_secs: self.fail_timeout(weight),
+            ..*self
+        }
+    }
+}
+
+/// Failures during the recovery grace window count at 1/N rate.
+const GRACE_DIVISOR: u32 = 2;
+
+/// Passive health state of a peer.
+///
+/// A peer is either up, ejected, or half-open once its
+/// ejection times out. A half-open peer is let through
+/// once per `fail_timeout_secs`, until it succeeds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PeerHealth {
+    pub fails: u32,
+    /// When the peer was ejected, or last let through while half-open.
+    pub ejected: Option<u32>,
+    /// When the peer last recovered from an ejection.
+    pub recovered: Option<u32>,
+    pub grace_fails: u32,
+    /// Why the peer was last ejected.
+    pub reason: Option<EjectionReason>,
+    /// Ejections since the peer last stayed up.
+    pub ejections: u32,
+    /// Kept out for `parked_secs`.
+    pub parked: bool,
+}
+
+impl PeerHealth {
+    /// Whether the peer is kept out of rotation.
+    pub fn is_ejected(&self, config: &HealthConfig, now: u32) -> bool {
+        self.ejected
+            .is_some_and(|at| now.saturating_sub(at) <= self.timeout(config))
+    }
+
+    /// How long the peer is kept out once ejected.
+    pub fn timeout(&self, config: &HealthConfig) -> u32 {
+        match self.parked {
+            true => config.parked_secs.max(config.fail_timeout_secs),
+            false => config.fail_timeout_secs,
+        }
+    }
+
+    /// Whether the ejection has timed out, but the peer has not succeeded yet.
+    pub fn is_half_open(&self, config: &HealthConfig, now: u32) -> bool {
+        self.ejected.is_some() && !self.is_ejected(config, now)
+    }
+
+    /// The peer is selected.
+    pub fn on_selected(&mut self, config: &HealthConfig, now: u32) {
+        // only one try per timeout
+        if self.is_half_open(config, now) {
+            self.ejected = Some(now);
+        }
+    }
+
+    pub fn on_success(&mut self, config: &HealthConfig, now: u32) {
+        // stayed up
+        if self.ejected.is_none()
+            && self
+                .recovered
+                .is_none_or(|at| now.saturating_sub(at) >= config.fail_timeout_secs)
+        {
+            self.ejections = 0;
+        }
+        if self.ejected.take().is_some() {
+            self.parked = false;
+            self.recovered = Some(now);
+            self.grace_fails = 0;
+            self.reason = None;
+        }
+        self.fails = match config.success_credit {
+            0 => 0,
+            credit => self.fails.saturating_sub(credit),
+        };
+    }
+
+    /// Count a failure `points` times.
+    /// Return false if the failure is not counted.
+    pub fn on_failure(&mut self, config: &HealthConfig, now: u32, points: u32) -> bool {
+        // right after a recovery, only every other failure counts
+        let in_grace = self
+            .recovered
+            .is_some_and(|at| now.saturating_sub(at) < config.recovery_grace_secs);
+        if in_grace {
+            self.grace_fails += 1;
+            if !self.grace_fails.is_multiple_of(GRACE_DIVISOR) {
+                return false;
+            }
+        }
+
+        self.fails = self.fails.saturating_add(points);
+        if self.fails >= config.max_fails {
+            self.set_ejected(config, now);
+        }
+        true
+    }
+
+    /// Eject at once.
+    pub fn eject(&mut self, config: &HealthConfig, now: u32) {
+        self.fails = self.fails.max(config.max_fails);
+        self.set_ejected(config, now);
+    }
+
+    fn set_ejected(&mut self, config: &HealthConfig, now: u32) {
+        // not again while ejected or half-open
+        if self.ejected.is_none() {
+            self.ejections = self.ejections.saturating_add(1);
+            let max = config.max_consecutive_ejections;
+            self.parked = max != 0 && self.ejections >= max;
+        }
+        self.ejected = Some(now);
+    }
+}
+
+/// Why a peer was ejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EjectionReason {
+    /// Reported failures, the class of the last one if classified.
+    Failures(Option<FailureClass>),
+    /// An active health check probe failed.
+    ProbeFailed,
+    /// Reported unhealthy by the application, for a while.
+    Reported,
+}
+
+/// Peer health state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NodeState {
+    Healthy,
+    Ejected,
+}
+
+/// Pool health state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PoolStatus {
+    /// No peer is ejected.
+    Healthy,
+    /// Some peers are ejected, enough are left.
+    Degraded,
+    /// Too few peers are left, or none at all.
+    Down,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_ejected_at_zero() {
+        let config = HealthConfig {
+            max_fails: 2,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let mut health = PeerHealth::default();
+
+        health.on_failure(&config, 0, 1);
+        assert!(!health.is_ejected(&config, 0));
+
+        // ejected at time 0 is still ejected
+        health.on_failure(&config, 0, 1);
+        assert_eq!(health.ejected, Some(0));
+        assert!(health.is_ejected(&config, 5));
+
+        // then recovering, not dead, even with fails >= max_fails
+        assert!(health.fails >= config.max_fails);
+        assert!(health.is_half_open(&config, 11));
+        assert!(!health.is_ejected(&config, 11));
+
+        // one try per timeout
+        health.on_selected(&config, 11);
+        assert!(health.is_ejected(&config, 12));
+
+        health.on_success(&config, 12);
+        assert_eq!(health.fails, 0);
+        assert_eq!(health.ejected, None);
+        assert_eq!(health.recovered, Some(12));
+        assert!(!health.is_ejected(&config, 12));
+    }
+
+    #[test]
+    fn health_success_credit() {
+        let config = HealthConfig {
+            max_fails: 10,
+            success_credit: 1,
+            ..Default::default()
+        };
+        let mut health = PeerHealth::default();
+        for _ in 0..4 {
+            health.on_failure(&config, 0, 1);
+        }
+        assert_eq!(health.fails, 4);
+
+        for left in (0..4).rev() {
+            health.on_success(&config, 1);
+            assert_eq!(health.fails, left);
+        }
+        health.on_success(&config, 1);
+        assert_eq!(health.fails, 0);
+
+        // still ejected by a failure right after recovering
+        let config = HealthConfig {
+            max_fails: 3,
+            success_credit: 1,
+            ..Default::default()
+        };
+        let mut health = PeerHealth::default();
+        for _ in 0..3 {
+            health.on_failure(&config, 0, 1);
+        }
+        assert!(health.is_ejected(&config, 0));
+        health.on_success(&config, 20);
+        assert_eq!(health.ejected, None);
+        assert_eq!(health.fails, 2);
+        health.on_failure(&config, 21, 1);
+        assert!(health.is_ejected(&config, 21));
+    }
+
+    #[test]
+    fn health_validate() {
+        assert_eq!(HealthConfig::default().validate(), Ok(()));
+
+        let config = HealthConfig {
+            max_fails: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(BalanceError::InvalidConfig(_))));
+
+        let config = HealthConfig {
+            fail_timeout_secs: 0,
+            ..Default::default()
+        };
+        assert!(matches!(config.validate(), Err(BalanceError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,99 @@
+/// Values below are exact.
+const LINEAR: u64 = 16;
+/// Sub buckets per power of two above.
+const SUB_BITS: u32 = 4;
+/// Values are capped to 2^MAX_EXP - 1, about 19 hours in micros.
+const MAX_EXP: u32 = 36;
+const BUCKETS: usize = LINEAR as usize + ((MAX_EXP - SUB_BITS) << SUB_BITS) as usize;
+
+/// Log-linear histogram, within 1/16 of the true value.
+#[derive(Debug, Clone)]
+pub(crate) struct Histogram {
+    counts: Box<[u32]>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            total: 0,
+        }
+    }
+}
+
+fn index(value: u64) -> usize {
+    let value = value.min((1 << MAX_EXP) - 1);
+    if value < LINEAR {
+        return value as usize;
+    }
+
+    let exp = u64::BITS - 1 - value.leading_zeros();
+    let sub = (value >> (exp - SUB_BITS)) & (LINEAR - 1);
+    LINEAR as usize + (((exp - SUB_BITS) << SUB_BITS) as usize | sub as usize)
+}
+
+/// Middle of a bucket.
+fn value(index: usize) -> u64 {
+    if index < LINEAR as usize {
+        return index as u64;
+    }
+
+    let index = index - LINEAR as usize;
+    let exp = (index >> SUB_BITS) as u32 + SUB_BITS;
+    let sub = (index as u64) & (LINEAR - 1);
+    let width = 1 << (exp - SUB_BITS);
+    (1 << exp) + sub * width + width / 2
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let count = &mut self.counts[index(value)];
+        *count = count.saturating_add(1);
+        self.total += 1;
+    }
+
+    /// Value at quantile `q`, from 0.0 to 1.0, None if empty.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += *count as u64;
+            if seen >= rank {
+                return Some(value(i));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_quantile() {
+        let mut hist = Histogram::default();
+        assert_eq!(hist.quantile(0.5), None);
+
+        for v in 1..=10_000 {
+            hist.record(v);
+        }
+
+        for (q, exact) in [(0.5, 5000.0), (0.95, 9500.0), (0.99, 9900.0)] {
+            let v = hist.quantile(q).unwrap() as f64;
+            assert!((v - exact).abs() / exact <= 1.0 / 16.0, "q{}: {}", q, v);
+        }
+
+        // small values are exact, huge ones are capped
+        let mut hist = Histogram::default();
+        hist.record(7);
+        assert_eq!(hist.quantile(1.0), Some(7));
+        hist.record(u64::MAX);
+        assert!(hist.quantile(1.0).unwrap() < 1 << MAX_EXP);
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::Token;
+
+/// A past selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionRecord {
+    /// When it was made, in seconds.
+    pub at: u32,
+    /// None if no peer was available.
+    pub token: Option<Token>,
+    /// Peers not ejected at the time.
+    pub healthy: u8,
+}
+
+/// Last selections, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    len: usize,
+    records: Mutex<VecDeque<SelectionRecord>>,
+}
+
+impl History {
+    /// Keep up to `len` selections, 0 disables it.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            records: Mutex::new(VecDeque::with_capacity(len)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.len > 0
+    }
+
+    pub fn push(&self, record: SelectionRecord) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.len {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    pub fn records(&self) -> Vec<SelectionRecord> {
+        self.records.lock().unwrap().iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_bounded() {
+        let history = History::new(3);
+        for i in 0..5 {
+            history.push(SelectionRecord {
+                at: i,
+                token: Some(Token(i as u8)),
+                healthy: 1,
+            });
+        }
+        let at: Vec<u32> = history.records().iter().map(|x| x.at).collect();
+        assert_eq!(at, vec![2, 3, 4]);
+
+        let history = History::new(0);
+        history.push(SelectionRecord {
+            at: 0,
+            token: None,
+            healthy: 0,
+        });
+        assert!(history.records().is_empty());
+    }
+}
@@ -1,6 +1,11 @@
 use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
 
-use super::{Balance, Token};
+use super::{Balance, Strategy, Token};
+use crate::clock::{Clock, SystemClock};
+use crate::failure::{FailureAction, FailureClass};
+use crate::health::{HealthConfig, PeerHealth};
+use crate::round_robin::RoundRobin;
 
 /// Iphash node.
 #[derive(Debug)]
@@ -13,7 +18,19 @@ struct Node {
 #[derive(Debug)]
 pub struct IpHash {
     nodes: Vec<Node>,
+    weights: Vec<u8>,
     total: u8,
+    // bits of an address to hash
+    prefix_v4: u8,
+    prefix_v6: u8,
+    // mixed into the hash of an address
+    hash_seed: u64,
+    // used when there is no address to hash
+    fallback: Option<RoundRobin>,
+    health: Option<HealthConfig>,
+    // indexed by token
+    peers: Mutex<Vec<PeerHealth>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Balance for IpHash {
@@ -23,24 +40,96 @@ impl Balance for IpHash {
         self.total
     }
 
+    fn strategy_name(&self) -> &'static str {
+        Strategy::IpHash.name()
+    }
+
     fn new(weights: &[u8]) -> Self {
+        if weights.len() <= 1 {
+            return Self::with_vnodes(weights, |_| 0);
+        }
+
+        let ratio = replica_ratio(weights) as usize;
+        Self::with_vnodes(weights, |weight| weight as usize * ratio + 1)
+    }
+
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        // an empty pool, not a phantom peer
+        if self.total == 0 {
+            return None;
+        }
+
+        let Some(health) = self.health else {
+            return Some(match self.total {
+                1 => Token(0),
+                _ => self.nodes[self.locate(state)].token,
+            });
+        };
+
+        // the next peer on the ring that is not ejected,
+        // a single peer has no ring
+        let now = self.clock.now();
+        let mut peers = self.peers.lock().unwrap();
+        let token = match self.total {
+            1 => (!peers[0].is_ejected(&health, now)).then_some(Token(0)),
+            _ => self
+                .ring(self.locate(state))
+                .find(|x| !peers[x.0 as usize].is_ejected(&health, now)),
+        }?;
+        peers[token.0 as usize].on_selected(&health, now);
+        Some(token)
+    }
+
+    fn on_success(&self, token: Token) {
+        let Some(health) = self.health else {
+            return;
+        };
+
+        let now = self.clock.now();
+        if let Some(p) = self.peers.lock().unwrap().get_mut(token.0 as usize) {
+            p.on_success(&health, now);
+        }
+    }
+
+    fn on_failure(&self, token: Token) {
+        self.fail(token, FailureAction::Count(1));
+    }
+
+    fn on_failure_classified(&self, token: Token, class: FailureClass) {
+        if let Some(health) = self.health {
+            self.fail(token, health.failure_policy.get(class));
+        }
+    }
+}
+
+impl IpHash {
+    /// Constructor with a custom weight to virtual nodes mapping,
+    /// e.g. log-scaled or capped for very heavy peers.
+    pub fn with_vnodes<F: Fn(u8) -> usize>(weights: &[u8], vnodes: F) -> Self {
         assert!(weights.len() <= u8::MAX as usize);
 
         if weights.len() <= 1 {
             return Self {
                 nodes: Vec::new(),
+                weights: weights.to_vec(),
                 total: weights.len() as u8,
+                prefix_v4: 32,
+                prefix_v6: 128,
+                hash_seed: 0,
+                fallback: Some(RoundRobin::new(weights)),
+                health: None,
+                peers: Mutex::new(vec![PeerHealth::default(); weights.len()]),
+                clock: Arc::new(SystemClock),
             };
         }
 
-        let ratio = replica_ratio(weights);
-        let count = weights.iter().map(|x| *x as usize * ratio as usize).sum();
+        let count = weights.iter().map(|x| vnodes(*x)).sum();
         let mut nodes: Vec<Node> = Vec::with_capacity(count);
 
-        for (n, weight) in weights.iter().map(|x| *x as usize * ratio as usize).enumerate() {
+        for (n, count) in weights.iter().map(|x| vnodes(*x)).enumerate() {
             let token = Token(n as u8);
 
-            for vidx in 0..=weight {
+            for vidx in 0..count {
                 let buf = format!("{0} 114514", vidx);
                 let hash = chash(buf.as_bytes());
                 nodes.push(Node { hash, token });
@@ -51,27 +140,155 @@ impl Balance for IpHash {
 
         Self {
             nodes,
+            weights: weights.to_vec(),
             total: weights.len() as u8,
+            prefix_v4: 32,
+            prefix_v6: 128,
+            hash_seed: 0,
+            fallback: Some(RoundRobin::new(weights)),
+            health: None,
+            peers: Mutex::new(vec![PeerHealth::default(); weights.len()]),
+            clock: Arc::new(SystemClock),
         }
     }
 
-    fn next(&self, state: &Self::State) -> Option<Token> {
+    /// Skip ejected peers, and select the next one on the ring,
+    /// see [`preferences`](Self::preferences).
+    ///
+    /// # Panics
+    ///
+    /// If `health` is invalid, see [`HealthConfig::validate`].
+    pub fn with_health(self, health: HealthConfig) -> Self {
+        if let Err(e) = health.validate() {
+            panic!("{}", e);
+        }
+        Self {
+            health: Some(health),
+            ..self
+        }
+    }
+
+    /// Get the passive health check config.
+    pub fn health_config(&self) -> Option<HealthConfig> {
+        self.health
+    }
+
+    /// Use another time source for the health check.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Get the first `k` distinct peers `ip` maps to, in order, regardless
+    /// of their health. [`next`](Balance::next) selects the first of them
+    /// that is not ejected, so a client can retry the next one on failure.
+    pub fn preferences(&self, ip: &IpAddr, k: usize) -> Vec<Token> {
         if self.total <= 1 {
-            return Some(Token(0));
+            return (0..self.total).map(Token).take(k).collect();
         }
+        self.ring(self.locate(ip)).take(k).collect()
+    }
 
-        let hash = match state {
-            IpAddr::V4(x) => chash_for_ip(&x.octets()),
-            IpAddr::V6(x) => chash_for_ip(&x.octets()),
+    /// Index of the first virtual node of `ip`.
+    fn locate(&self, ip: &IpAddr) -> usize {
+        let hash = match ip {
+            IpAddr::V4(x) => chash_for_ip(&mask(x.octets(), self.prefix_v4), self.hash_seed),
+            IpAddr::V6(x) => chash_for_ip(&mask(x.octets(), self.prefix_v6), self.hash_seed),
         };
 
-        let idx = match self.nodes.binary_search_by_key(&hash, |node| node.hash) {
+        match self.nodes.binary_search_by_key(&hash, |node| node.hash) {
             Ok(idx) => idx,
-            Err(idx) if idx >= self.nodes.len() as usize => 0,
+            Err(idx) if idx >= self.nodes.len() => 0,
             Err(idx) => idx,
+        }
+    }
+
+    /// Distinct peers walking the ring from `idx`.
+    fn ring(&self, idx: usize) -> impl Iterator<Item = Token> + '_ {
+        let len = self.nodes.len();
+        let mut seen = [false; u8::MAX as usize + 1];
+        (0..len)
+            .map(move |i| self.nodes[(idx + i) % len].token)
+            .filter(move |x| !std::mem::replace(&mut seen[x.0 as usize], true))
+    }
+
+    fn fail(&self, token: Token, action: FailureAction) {
+        let Some(health) = self.health else {
+            return;
         };
 
-        Some(self.nodes[idx].token)
+        let now = self.clock.now();
+        let mut peers = self.peers.lock().unwrap();
+        let Some(p) = peers.get_mut(token.0 as usize) else {
+            return;
+        };
+        match action {
+            FailureAction::Ignore => {}
+            FailureAction::Count(points) => {
+                p.on_failure(&health, now, points);
+            }
+            FailureAction::Eject => p.eject(&health, now),
+        }
+    }
+
+    /// Whether to round robin when there is no address to hash,
+    /// see [`next_or_fallback`](Self::next_or_fallback). Allowed by default.
+    pub fn with_fallback(self, allow: bool) -> Self {
+        let fallback = allow.then(|| RoundRobin::new(&self.weights));
+        Self { fallback, ..self }
+    }
+
+    /// Whether to round robin when there is no address to hash.
+    pub fn allows_fallback(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    /// Select by the hash of `ip`, round robin if it is unknown,
+    /// e.g. masked by a proxy.
+    ///
+    /// Return None without an address if the fallback is not allowed.
+    pub fn next_or_fallback(&self, ip: Option<&IpAddr>) -> Option<Token> {
+        match ip {
+            Some(ip) => self.next(ip),
+            None => self.fallback.as_ref()?.next(&()),
+        }
+    }
+
+    /// Only hash the first `len` bits of an IPv4 address,
+    /// so that clients of the same subnet stick to the same peer.
+    pub fn with_prefix_v4(self, len: u8) -> Self {
+        Self {
+            prefix_v4: len.min(32),
+            ..self
+        }
+    }
+
+    /// Only hash the first `len` bits of an IPv6 address,
+    /// so that clients of the same subnet stick to the same peer.
+    pub fn with_prefix_v6(self, len: u8) -> Self {
+        Self {
+            prefix_v6: len.min(128),
+            ..self
+        }
+    }
+
+    /// Hash addresses with `seed`, 0 by default. Instances with the same
+    /// seed and weights map every address to the same peer, across
+    /// processes and hosts.
+    pub fn with_hash_seed(self, seed: u64) -> Self {
+        Self {
+            hash_seed: seed,
+            ..self
+        }
+    }
+
+    /// Get the seed addresses are hashed with.
+    pub fn hash_seed(&self) -> u64 {
+        self.hash_seed
+    }
+
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        self.weights.clone()
     }
 }
 
@@ -82,13 +299,13 @@ mod chash {
 
     macro_rules! c_add {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_add($b) }
+            $a.wrapping_add($b)
         };
     }
 
     macro_rules! c_mul {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_mul($b) }
+            $a.wrapping_mul($b)
         };
     }
 
@@ -128,8 +345,9 @@ mod chash {
         h
     }
 
-    pub fn chash_for_ip(buf: &[u8]) -> u32 {
-        let mut h = SEED ^ c_mul!(buf.len() as u32, M);
+    pub fn chash_for_ip(buf: &[u8], seed: u64) -> u32 {
+        let seed = seed as u32 ^ (seed >> 32) as u32;
+        let mut h = SEED ^ seed ^ c_mul!(buf.len() as u32, M);
 
         let (_, buf, _) = unsafe { buf.align_to::<u32>() };
 
@@ -143,6 +361,15 @@ mod chash {
     }
 }
 
+/// Clear all bits after the first `prefix` bits.
+fn mask<const N: usize>(mut octets: [u8; N], prefix: u8) -> [u8; N] {
+    for (i, b) in octets.iter_mut().enumerate() {
+        let bits = (prefix as usize).saturating_sub(i * 8).min(8) as u32;
+        *b &= !0xffu8.checked_shr(bits).unwrap_or(0);
+    }
+    octets
+}
+
 fn replica_ratio(weights: &[u8]) -> u8 {
     const MIN_REPLICA: u8 = 128;
 
@@ -212,7 +439,7 @@ mod tests {
         macro_rules! run {
             ($ip: expr) => {{
                 let b = $ip.octets();
-                assert_eq!(chash(&b), chash_for_ip(&b));
+                assert_eq!(chash(&b), chash_for_ip(&b, 0));
             }};
             (=> $ip: expr) => {{
                 let ip = $ip.parse::<Ipv6Addr>().unwrap();
@@ -315,4 +542,169 @@ mod tests {
         println!("max diff: {}", max_diff.max());
         println!("mean diff: {}", mean_diff.mean());
     }
+
+    #[test]
+    fn ih_custom_vnodes() {
+        let log_scale = |weight: u8| (weight as f64).log2() as usize * 32 + 32;
+        let iphash = IpHash::with_vnodes(&[1, 2, 4, 8], log_scale);
+
+        for (i, count) in [32, 64, 96, 128].into_iter().enumerate() {
+            let vnodes = iphash.nodes.iter().filter(|x| x.token == Token(i as u8)).count();
+            assert_eq!(vnodes, count);
+        }
+
+        let mut distro = [0f64; 4];
+        for ip in (0..=u32::MAX).step_by(65537).map(Ipv4Addr::from).map(IpAddr::from) {
+            let token = iphash.next(&ip).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+
+        println!("{:?}", distro);
+        assert!(distro.windows(2).all(|x| x[0] < x[1]));
+    }
+
+    #[test]
+    fn ih_prefix() {
+        assert_eq!(mask([255; 4], 24), [255, 255, 255, 0]);
+        assert_eq!(mask([255; 4], 20), [255, 255, 0xf0, 0]);
+        assert_eq!(mask([255; 4], 32), [255; 4]);
+        assert_eq!(mask([255; 4], 0), [0; 4]);
+
+        let iphash = IpHash::new(&[1; 8]).with_prefix_v4(24).with_prefix_v6(64);
+
+        // same /24, same peer
+        for i in 0..=255u8 {
+            let ip1 = IpAddr::from(Ipv4Addr::new(10, i, 7, 1));
+            let ip2 = IpAddr::from(Ipv4Addr::new(10, i, 7, 200));
+            assert_eq!(iphash.next(&ip1), iphash.next(&ip2));
+        }
+
+        // different /24s, spread across peers
+        let mut hit = [false; 8];
+        for i in 0..=255u8 {
+            let ip = IpAddr::from(Ipv4Addr::new(10, i, 7, 1));
+            hit[iphash.next(&ip).unwrap().0 as usize] = true;
+        }
+        assert!(hit.iter().all(|x| *x));
+
+        // same /64, same peer
+        let ip1 = "2001:db8:1:2::1".parse::<IpAddr>().unwrap();
+        let ip2 = "2001:db8:1:2:ffff::9".parse::<IpAddr>().unwrap();
+        assert_eq!(iphash.next(&ip1), iphash.next(&ip2));
+
+        let mut hit = [false; 8];
+        for i in 0..=255u16 {
+            let ip = IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, i, 0, 0, 0, 0, 1));
+            hit[iphash.next(&ip).unwrap().0 as usize] = true;
+        }
+        assert!(hit.iter().all(|x| *x));
+
+        // full address by default
+        let iphash = IpHash::new(&[1; 8]);
+        let differ = (0..=255u8)
+            .filter(|i| {
+                let ip1 = IpAddr::from(Ipv4Addr::new(10, *i, 7, 1));
+                let ip2 = IpAddr::from(Ipv4Addr::new(10, *i, 7, 200));
+                iphash.next(&ip1) != iphash.next(&ip2)
+            })
+            .count();
+        assert!(differ > 0);
+    }
+
+    #[test]
+    fn ih_fallback() {
+        let iphash = IpHash::new(&[1, 1, 1, 1]);
+        assert!(iphash.allows_fallback());
+
+        let mut distro = [0; 4];
+        for _ in 0..400 {
+            let token = iphash.next_or_fallback(None).unwrap();
+            distro[token.0 as usize] += 1;
+        }
+        assert_eq!(distro, [100; 4]);
+
+        // still hashed with an address
+        let ip = "1.1.1.1".parse::<IpAddr>().unwrap();
+        assert_eq!(iphash.next_or_fallback(Some(&ip)), iphash.next(&ip));
+
+        let iphash = iphash.with_fallback(false);
+        assert!(!iphash.allows_fallback());
+        assert_eq!(iphash.next_or_fallback(None), None);
+        assert_eq!(iphash.next_or_fallback(Some(&ip)), iphash.next(&ip));
+    }
+
+    #[test]
+    fn ih_preferences() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let iphash = IpHash::new(&[1; 8])
+            .with_health(HealthConfig::default())
+            .with_clock(clock.clone());
+        let ip = "114.51.4.19".parse::<IpAddr>().unwrap();
+
+        let prefs = iphash.preferences(&ip, 3);
+        assert_eq!(prefs.len(), 3);
+        assert!(prefs[0] != prefs[1] && prefs[1] != prefs[2] && prefs[0] != prefs[2]);
+        assert_eq!(iphash.preferences(&ip, 3), prefs);
+        assert_eq!(iphash.preferences(&ip, 8).len(), 8);
+        assert_eq!(iphash.preferences(&ip, 16).len(), 8);
+        assert_eq!(iphash.next(&ip), Some(prefs[0]));
+
+        // fail over along the list
+        iphash.on_failure(prefs[0]);
+        assert_eq!(iphash.next(&ip), Some(prefs[1]));
+        iphash.on_failure(prefs[1]);
+        assert_eq!(iphash.next(&ip), Some(prefs[2]));
+        assert_eq!(iphash.preferences(&ip, 3), prefs);
+
+        // back to the first once it recovers
+        clock.advance(11);
+        assert_eq!(iphash.next(&ip), Some(prefs[0]));
+        iphash.on_success(prefs[0]);
+        assert_eq!(iphash.next(&ip), Some(prefs[0]));
+
+        assert_eq!(IpHash::new(&[1]).preferences(&ip, 3), vec![Token(0)]);
+        assert!(IpHash::new(&[]).preferences(&ip, 3).is_empty());
+    }
+
+    #[test]
+    fn ih_single_peer_health() {
+        use crate::clock::MockClock;
+
+        let ip = "114.51.4.19".parse::<IpAddr>().unwrap();
+        assert_eq!(IpHash::new(&[]).next(&ip), None);
+        assert_eq!(IpHash::new(&[1]).next(&ip), Some(Token(0)));
+
+        let clock = Arc::new(MockClock::new(100));
+        let iphash = IpHash::new(&[1])
+            .with_health(HealthConfig::default())
+            .with_clock(clock.clone());
+        iphash.on_failure(Token(0));
+        assert_eq!(iphash.next(&ip), None);
+        clock.advance(11);
+        assert_eq!(iphash.next(&ip), Some(Token(0)));
+    }
+
+    #[test]
+    fn ih_hash_seed() {
+        use std::net::Ipv4Addr;
+
+        let weights = [1, 2, 3, 4, 5, 6, 7, 8];
+        let a = IpHash::new(&weights).with_hash_seed(0x5eed);
+        let b = IpHash::new(&weights).with_hash_seed(0x5eed);
+        let c = IpHash::new(&weights).with_hash_seed(0x5eee);
+        assert_eq!(a.hash_seed(), 0x5eed);
+        assert_eq!(IpHash::new(&weights).hash_seed(), 0);
+
+        let mut moved = 0;
+        for i in 0..10000u32 {
+            let ip = IpAddr::V4(Ipv4Addr::from(i.wrapping_mul(2654435761)));
+            assert_eq!(a.next(&ip), b.next(&ip));
+            if a.next(&ip) != c.next(&ip) {
+                moved += 1;
+            }
+        }
+        assert!(moved > 5000, "{}", moved);
+    }
 }
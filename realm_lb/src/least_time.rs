@@ -0,0 +1,341 @@
+use std::sync::Mutex;
+
+use super::{Balance, Strategy, Token};
+use crate::histogram::Histogram;
+
+/// Weight of a new sample in the moving average.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// Latency to select by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LatencyMetric {
+    /// Moving average.
+    #[default]
+    Ewma,
+    P50,
+    P95,
+    P99,
+}
+
+/// Least-time node.
+#[derive(Debug)]
+struct Node {
+    weight: u8,
+    conns: u32,
+    ewma: Option<f64>,
+    // of connection setup, not selected by
+    connect_ewma: Option<f64>,
+    // only with quantiles enabled
+    hist: Option<Histogram>,
+    token: Token,
+}
+
+impl Node {
+    fn latency(&self, metric: LatencyMetric) -> Option<f64> {
+        let q = match metric {
+            LatencyMetric::Ewma => return self.ewma,
+            LatencyMetric::P50 => 0.5,
+            LatencyMetric::P95 => 0.95,
+            LatencyMetric::P99 => 0.99,
+        };
+        self.hist.as_ref()?.quantile(q).map(|x| x as f64)
+    }
+}
+
+/// Latency of a peer, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LatencyStat {
+    pub token: Token,
+    /// None until the first report.
+    pub ewma_micros: Option<u64>,
+    /// None unless quantiles are tracked.
+    pub p50_micros: Option<u64>,
+    pub p95_micros: Option<u64>,
+    pub p99_micros: Option<u64>,
+    /// Moving average of connection setup time, None until the first report.
+    pub connect_ewma_micros: Option<u64>,
+}
+
+/// Least response time balancer.
+///
+/// Select the peer with the lowest `latency * (conns + 1) / weight`.
+/// A peer without reports is assumed to be as fast as the average one.
+#[derive(Debug)]
+pub struct LeastTime {
+    nodes: Mutex<Vec<Node>>,
+    metric: LatencyMetric,
+    total: u8,
+}
+
+impl Balance for LeastTime {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.total
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::LeastTime.name()
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        let nodes = weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Node {
+                weight: *w,
+                conns: 0,
+                ewma: None,
+                connect_ewma: None,
+                hist: None,
+                token: Token(i as u8),
+            })
+            .collect();
+
+        Self {
+            nodes: Mutex::new(nodes),
+            metric: LatencyMetric::default(),
+            total: weights.len() as u8,
+        }
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        // an empty pool, not a phantom peer
+        match self.total {
+            0 => return None,
+            1 => return Some(Token(0)),
+            _ => {}
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+
+        let known: Vec<f64> = nodes.iter().filter_map(|p| p.latency(self.metric)).collect();
+        let unknown = match known.len() {
+            0 => 1.0,
+            n => known.iter().sum::<f64>() / n as f64,
+        };
+
+        let mut best: Option<(f64, &mut Node)> = None;
+        for p in nodes.iter_mut().filter(|p| p.weight != 0) {
+            let latency = p.latency(self.metric).unwrap_or(unknown).max(1.0);
+            let score = latency * (p.conns as f64 + 1.0) / p.weight as f64;
+            match best {
+                Some((x, _)) if score >= x => {}
+                _ => best = Some((score, p)),
+            }
+        }
+
+        let (_, p) = best?;
+        p.conns += 1;
+        Some(p.token)
+    }
+
+    fn on_disconnect(&self, token: Token) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.conns = p.conns.saturating_sub(1);
+        }
+    }
+
+    fn on_latency(&self, token: Token, micros: u64) {
+        let mut nodes = self.nodes.lock().unwrap();
+        let Some(p) = nodes.get_mut(token.0 as usize) else {
+            return;
+        };
+
+        p.ewma = Some(ewma(p.ewma, micros));
+        if let Some(hist) = &mut p.hist {
+            hist.record(micros);
+        }
+    }
+
+    fn on_connect_time(&self, token: Token, micros: u64) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.connect_ewma = Some(ewma(p.connect_ewma, micros));
+        }
+    }
+}
+
+impl LeastTime {
+    /// Track p50/p95/p99 of every peer, see [`stats`](Self::stats).
+    pub fn with_quantiles(self) -> Self {
+        for p in self.nodes.lock().unwrap().iter_mut() {
+            p.hist.get_or_insert_with(Histogram::default);
+        }
+        self
+    }
+
+    /// Select by `metric`, a quantile also enables [`with_quantiles`](Self::with_quantiles).
+    pub fn with_metric(self, metric: LatencyMetric) -> Self {
+        let this = Self { metric, ..self };
+        match metric {
+            LatencyMetric::Ewma => this,
+            _ => this.with_quantiles(),
+        }
+    }
+
+    /// Get latency of all peers.
+    pub fn stats(&self) -> Vec<LatencyStat> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .map(|p| {
+                let quantile = |q| p.hist.as_ref().and_then(|x| x.quantile(q));
+                LatencyStat {
+                    token: p.token,
+                    ewma_micros: p.ewma.map(|x| x as u64),
+                    p50_micros: quantile(0.5),
+                    p95_micros: quantile(0.95),
+                    p99_micros: quantile(0.99),
+                    connect_ewma_micros: p.connect_ewma.map(|x| x as u64),
+                }
+            })
+            .collect()
+    }
+
+    /// Get the moving average latency of the pool, of every peer
+    /// with reports weighted by its weight, 0 if there is none.
+    pub fn pool_ewma_micros(&self) -> u64 {
+        let nodes = self.nodes.lock().unwrap();
+        let (sum, weights) = nodes
+            .iter()
+            .filter_map(|p| Some((p.ewma?, p.weight as f64)))
+            .fold((0.0, 0.0), |(sum, weights), (ewma, w)| (sum + ewma * w, weights + w));
+        if weights == 0.0 {
+            return 0;
+        }
+        (sum / weights) as u64
+    }
+
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| p.weight).collect()
+    }
+}
+
+/// Moving average with a new sample, the sample itself if there is none.
+pub(crate) fn ewma(avg: Option<f64>, sample: u64) -> f64 {
+    let sample = sample as f64;
+    avg.map_or(sample, |x| x + EWMA_ALPHA * (sample - x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mostly fast with a slow tail, ending fast
+    fn bimodal(lt: &LeastTime, token: Token) {
+        for i in 0..1000 {
+            let micros = if i % 10 == 0 && i < 980 { 100_000 } else { 500 };
+            lt.on_latency(token, micros);
+        }
+    }
+
+    #[test]
+    fn lt_quantiles() {
+        let lt = LeastTime::new(&[1, 1]).with_quantiles();
+        bimodal(&lt, Token(0));
+
+        let stat = lt.stats()[0];
+        println!("{:?}", stat);
+        let ewma = stat.ewma_micros.unwrap();
+        let p50 = stat.p50_micros.unwrap();
+        let p95 = stat.p95_micros.unwrap();
+        assert!(ewma < 5_000);
+        assert!(p50 < 600);
+        assert!(p95 > 90_000);
+        assert!(stat.p99_micros.unwrap() >= p95);
+
+        // not tracked by default
+        let lt = LeastTime::new(&[1, 1]);
+        lt.on_latency(Token(0), 500);
+        assert_eq!(lt.stats()[0].ewma_micros, Some(500));
+        assert_eq!(lt.stats()[0].p95_micros, None);
+    }
+
+    #[test]
+    fn lt_select_by_metric() {
+        fn run(metric: LatencyMetric) -> [u32; 2] {
+            let lt = LeastTime::new(&[1, 1]).with_metric(metric);
+            bimodal(&lt, Token(0));
+            for _ in 0..1000 {
+                lt.on_latency(Token(1), 5_000);
+            }
+
+            let mut distro = [0; 2];
+            for _ in 0..100 {
+                let token = lt.next(&()).unwrap();
+                distro[token.0 as usize] += 1;
+                lt.on_disconnect(token);
+            }
+            distro
+        }
+
+        // good average, bad tail
+        assert_eq!(run(LatencyMetric::Ewma), [100, 0]);
+        assert_eq!(run(LatencyMetric::P95), [0, 100]);
+    }
+
+    #[test]
+    fn lt_least_outstanding() {
+        let lt = LeastTime::new(&[2, 1]);
+        let mut distro = [0; 2];
+        for _ in 0..300 {
+            distro[lt.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [200, 100]);
+    }
+
+    #[test]
+    fn lt_connect_time() {
+        let lt = LeastTime::new(&[1, 1]);
+        for _ in 0..100 {
+            lt.on_latency(Token(0), 50_000);
+            lt.on_connect_time(Token(0), 2_000);
+            lt.on_connect_time(Token(1), 300);
+        }
+
+        let stats = lt.stats();
+        assert_eq!(stats[0].ewma_micros, Some(50_000));
+        assert_eq!(stats[0].connect_ewma_micros, Some(2_000));
+        assert_eq!(stats[1].ewma_micros, None);
+        assert_eq!(stats[1].connect_ewma_micros, Some(300));
+
+        // a slow handshake moves the average
+        for _ in 0..10 {
+            lt.on_connect_time(Token(1), 10_300);
+        }
+        let stat = lt.stats()[1];
+        assert!(stat.connect_ewma_micros.unwrap() > 8_000);
+        assert_eq!(stat.ewma_micros, None);
+
+        // also in a round robin snapshot
+        let rr = crate::round_robin::RoundRobin::new(&[1, 1]);
+        rr.on_connect_time(Token(1), 700);
+        let nodes = rr.snapshot().nodes;
+        assert_eq!(nodes[0].connect_ewma_micros, None);
+        assert_eq!(nodes[1].connect_ewma_micros, Some(700));
+    }
+
+    #[test]
+    fn lt_pool_ewma() {
+        let lt = LeastTime::new(&[3, 1, 2, 0]);
+        assert_eq!(lt.pool_ewma_micros(), 0);
+
+        lt.on_latency(Token(0), 1_000);
+        lt.on_latency(Token(1), 5_000);
+        lt.on_latency(Token(3), 100_000);
+        assert_eq!(lt.pool_ewma_micros(), (3 * 1_000 + 5_000) / 4);
+
+        // moving with the peers
+        for _ in 0..100 {
+            lt.on_latency(Token(2), 10_000);
+        }
+        assert_eq!(lt.pool_ewma_micros(), (3 * 1_000 + 5_000 + 2 * 10_000) / 6);
+    }
+}
@@ -1,5 +1,6 @@
 /// Peer token.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token(pub u8);
 
 /// Load balance traits.
@@ -14,6 +15,37 @@ pub trait Balance {
 
     /// Total peers.
     fn total(&self) -> u8;
+
+    /// Name of the strategy, e.g. for logs and metric labels.
+    fn strategy_name(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Report a closed connection to a peer.
+    fn on_disconnect(&self, _token: Token) {}
+
+    /// Report a successful connection to a peer.
+    fn on_success(&self, _token: Token) {}
+
+    /// Report a failed connection to a peer.
+    fn on_failure(&self, _token: Token) {}
+
+    /// Report a failed connection to a peer, with its cause.
+    fn on_failure_classified(&self, token: Token, _class: FailureClass) {
+        self.on_failure(token)
+    }
+
+    /// Report the load a peer advertises about itself, from 0.0 (idle) to 1.0 (full).
+    fn on_reported_load(&self, _token: Token, _load: f32) {}
+
+    /// Report the free capacity a peer advertises about itself, from 0.0 (full) to 1.0 (empty).
+    fn on_reported_capacity(&self, _token: Token, _free: f32) {}
+
+    /// Report the response time of a peer.
+    fn on_latency(&self, _token: Token, _micros: u64) {}
+
+    /// Report the time to establish a connection to a peer, handshakes included.
+    fn on_connect_time(&self, _token: Token, _micros: u64) {}
 }
 
 /// Iphash impl.
@@ -22,5 +54,94 @@ pub mod ip_hash;
 /// Round-robin impl.
 pub mod round_robin;
 
+/// Fixed-size round-robin impl.
+pub mod array;
+
+/// Deficit round-robin impl.
+pub mod drr;
+
+/// Variance-minimizing impl.
+pub mod min_deviation;
+
+/// Adaptive load impl.
+pub mod adaptive_load;
+
+/// Free capacity impl.
+pub mod capacity;
+
+/// Least response time impl.
+pub mod least_time;
+
+/// Custom score impl.
+pub mod scored;
+
+/// Sticky sessions.
+pub mod sticky;
+
+/// Time source.
+pub mod clock;
+
+/// Passive health check.
+pub mod health;
+
+/// Peer specification.
+pub mod spec;
+
+/// Failure taxonomy.
+pub mod failure;
+
+/// Active health check.
+pub mod check;
+
+/// Balancer state snapshot.
+pub mod snapshot;
+
+/// Balancer errors.
+pub mod error;
+
+/// Selection log sampling.
+pub mod sample;
+
+/// Selection history.
+pub mod history;
+
+/// Change history.
+pub mod audit;
+
+/// Global concurrency limit.
+pub mod limit;
+
+/// Balancer config.
+pub mod config;
+
+/// Admin commands.
+pub mod admin;
+
+/// Call recording and replay.
+#[cfg(feature = "record")]
+pub mod record;
+
+mod canary;
+mod fast;
+mod histogram;
+mod notify;
+mod shed;
+mod trickle;
+
 mod balancer;
 pub use balancer::{Balancer, BalanceCtx, Strategy};
+pub use round_robin::{DrainPolicy, ProbationPolicy, RoundingPolicy, Selection};
+pub use health::{EjectionReason, HealthConfig, NodeState, PoolStatus};
+pub use check::ActiveHealthCheck;
+pub use limit::GlobalLimit;
+pub use config::{BalanceConfig, BalanceOptions};
+pub use admin::{Admin, AdminCommand, AdminResponse};
+pub use failure::{FailureAction, FailureClass, FailurePolicy};
+pub use error::{BalanceError, Timeout};
+pub use spec::{DuplicatePolicy, NodeSpec};
+pub use sample::LogSampling;
+pub use shed::ShedConfig;
+pub use sticky::{MemoryStore, SessionStore, Sticky};
+pub use history::SelectionRecord;
+pub use audit::{AuditEntry, AuditEvent};
+pub use snapshot::{BalanceSnapshot, NodeStat, NodeDelta, Transition};
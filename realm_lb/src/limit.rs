@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{Balance, FailureClass, Token};
+use crate::error::BalanceError;
+
+/// Concurrency cap across all peers of a balancer.
+///
+/// Each selection takes a permit, which is given back
+/// on [`on_disconnect`](Balance::on_disconnect). Once all permits
+/// are taken, nothing is selected, before any peer is touched.
+#[derive(Debug)]
+pub struct GlobalLimit<B> {
+    inner: B,
+    max: u32,
+    used: AtomicU32,
+}
+
+impl<B: Balance> GlobalLimit<B> {
+    /// Cap `inner` at `max` active connections.
+    pub fn with_limit(inner: B, max: u32) -> Self {
+        Self {
+            inner,
+            max,
+            used: AtomicU32::new(0),
+        }
+    }
+
+    /// Get the wrapped balancer.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Get permits in use.
+    pub fn in_use(&self) -> u32 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Same as [`next`](Balance::next), but tell why nothing is selected.
+    pub fn try_next(&self, state: &B::State) -> Result<Token, BalanceError> {
+        if !self.acquire() {
+            return Err(BalanceError::GlobalSaturated);
+        }
+
+        self.inner.next(state).ok_or_else(|| {
+            self.release();
+            BalanceError::NotEnoughPeers {
+                wanted: 1,
+                available: 0,
+            }
+        })
+    }
+
+    fn acquire(&self) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |x| (x < self.max).then_some(x + 1))
+            .is_ok()
+    }
+
+    fn release(&self) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |x| x.checked_sub(1));
+    }
+}
+
+impl<B: Balance> Balance for GlobalLimit<B> {
+    type State = B::State;
+
+    /// Constructor, without a cap.
+    fn new(weights: &[u8]) -> Self {
+        Self::with_limit(B::new(weights), u32::MAX)
+    }
+
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        self.try_next(state).ok()
+    }
+
+    fn total(&self) -> u8 {
+        self.inner.total()
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        self.inner.strategy_name()
+    }
+
+    fn on_disconnect(&self, token: Token) {
+        self.inner.on_disconnect(token);
+        self.release();
+    }
+
+    fn on_success(&self, token: Token) {
+        self.inner.on_success(token);
+    }
+
+    fn on_failure(&self, token: Token) {
+        self.inner.on_failure(token);
+    }
+
+    fn on_failure_classified(&self, token: Token, class: FailureClass) {
+        self.inner.on_failure_classified(token, class);
+    }
+
+    fn on_reported_load(&self, token: Token, load: f32) {
+        self.inner.on_reported_load(token, load);
+    }
+
+    fn on_reported_capacity(&self, token: Token, free: f32) {
+        self.inner.on_reported_capacity(token, free);
+    }
+
+    fn on_latency(&self, token: Token, micros: u64) {
+        self.inner.on_latency(token, micros);
+    }
+
+    fn on_connect_time(&self, token: Token, micros: u64) {
+        self.inner.on_connect_time(token, micros);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn limit_global_saturated() {
+        let lb = GlobalLimit::with_limit(RoundRobin::new(&[1, 1, 1]), 4);
+
+        let mut tokens: Vec<Token> = (0..4).map(|_| lb.next(&()).unwrap()).collect();
+        assert_eq!(lb.in_use(), 4);
+        assert_eq!(lb.next(&()), None);
+        assert_eq!(lb.try_next(&()), Err(BalanceError::GlobalSaturated));
+        // peers are not touched
+        assert_eq!(lb.inner().total_active_conns(), 4);
+
+        lb.on_disconnect(tokens.pop().unwrap());
+        assert_eq!(lb.in_use(), 3);
+        tokens.push(lb.next(&()).unwrap());
+        assert_eq!(lb.next(&()), None);
+
+        tokens.into_iter().for_each(|token| lb.on_disconnect(token));
+        assert_eq!(lb.in_use(), 0);
+        assert!((0..4).all(|_| lb.next(&()).is_some()));
+
+        // a failed selection gives the permit back
+        let lb = GlobalLimit::with_limit(RoundRobin::new(&[]), 1);
+        assert!(lb.next(&()).is_none());
+        assert_eq!(lb.in_use(), 0);
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt::{Debug, Formatter};
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::task::{Context, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Token;
+
+/// Wakers of pending tasks, woken all at once.
+#[derive(Debug, Default)]
+pub(crate) struct Notify {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Notify {
+    /// Register a task, to be woken by the next notification.
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    /// Wake all registered tasks.
+    pub fn notify_all(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Wakes a pending task once a deadline passes, so that a wait
+/// can time out without a runtime.
+///
+/// All deadlines share one timer thread, only spawned once a
+/// task is still pending on a first poll.
+#[derive(Debug)]
+pub(crate) struct Deadline {
+    at: Instant,
+    // key in the timer, once registered
+    key: Option<TimerKey>,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            key: None,
+        }
+    }
+
+    /// Whether the deadline passed, otherwise wake the task by then.
+    pub fn poll_expired(&mut self, cx: &Context<'_>) -> bool {
+        if Instant::now() >= self.at {
+            self.cancel();
+            return true;
+        }
+
+        let timer = Timer::get();
+        match self.key {
+            Some(key) => timer.update(key, cx.waker()),
+            None => self.key = Some(timer.insert(self.at, cx.waker().clone())),
+        }
+        false
+    }
+
+    fn cancel(&mut self) {
+        if let Some(key) = self.key.take() {
+            Timer::get().remove(key);
+        }
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        // done waiting, do not wake the task later
+        self.cancel();
+    }
+}
+
+// deadline, then registration order
+type TimerKey = (Instant, u64);
+
+/// Pending deadlines in order, served by one thread.
+#[derive(Default)]
+struct Timer {
+    // with the next registration number
+    wakers: Mutex<(BTreeMap<TimerKey, Waker>, u64)>,
+    changed: Condvar,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        let mut spawned = false;
+        let timer = TIMER.get_or_init(|| {
+            spawned = true;
+            Timer::default()
+        });
+        if spawned {
+            thread::Builder::new()
+                .name("lb-timer".into())
+                .spawn(move || timer.run())
+                .expect("failed to spawn the timer thread");
+        }
+        timer
+    }
+
+    fn insert(&self, at: Instant, waker: Waker) -> TimerKey {
+        let mut guard = self.wakers.lock().unwrap();
+        let (wakers, seq) = &mut *guard;
+        let key = (at, *seq);
+        *seq += 1;
+        let earliest = wakers.first_key_value().is_none_or(|(x, _)| key < *x);
+        wakers.insert(key, waker);
+        if earliest {
+            self.changed.notify_one();
+        }
+        key
+    }
+
+    fn update(&self, key: TimerKey, waker: &Waker) {
+        let mut guard = self.wakers.lock().unwrap();
+        if let Some(x) = guard.0.get_mut(&key) {
+            if !x.will_wake(waker) {
+                *x = waker.clone();
+            }
+        }
+    }
+
+    fn remove(&self, key: TimerKey) {
+        self.wakers.lock().unwrap().0.remove(&key);
+    }
+
+    fn run(&self) {
+        let mut expired = Vec::new();
+        let mut guard = self.wakers.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while let Some(entry) = guard.0.first_entry().filter(|x| x.key().0 <= now) {
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                // woken without the lock, the tasks may poll at once
+                drop(guard);
+                expired.drain(..).for_each(Waker::wake);
+                guard = self.wakers.lock().unwrap();
+                continue;
+            }
+            guard = match guard.0.first_key_value() {
+                Some(((at, _), _)) => {
+                    let timeout = at.saturating_duration_since(now);
+                    self.changed.wait_timeout(guard, timeout).unwrap().0
+                }
+                None => self.changed.wait(guard).unwrap(),
+            };
+        }
+    }
+}
+
+/// User callback on a peer, never called with a lock held.
+pub(crate) struct Hook(Box<dyn Fn(Token) + Send + Sync>);
+
+impl Hook {
+    pub fn new(f: impl Fn(Token) + Send + Sync + 'static) -> Self {
+        Self(Box::new(f))
+    }
+
+    pub fn call(&self, token: Token) {
+        (self.0)(token)
+    }
+}
+
+impl Debug for Hook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Hook")
+    }
+}
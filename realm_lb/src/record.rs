@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::{Balance, FailureClass, Token};
+use crate::clock::MockClock;
+
+/// A balancer call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Call {
+    Next,
+    Disconnect(Token),
+    Success(Token),
+    Failure(Token),
+    FailureClassified(Token, FailureClass),
+    ReportedLoad(Token, f32),
+}
+
+/// A recorded call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    /// When it was made, in seconds.
+    pub at: u32,
+    pub call: Call,
+    /// Selected peer of a [`Call::Next`], None otherwise.
+    pub outcome: Option<Token>,
+}
+
+/// Last calls, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    len: usize,
+    events: Mutex<VecDeque<Event>>,
+}
+
+impl Recorder {
+    /// Keep up to `len` calls, 0 disables it.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            events: Mutex::new(VecDeque::with_capacity(len)),
+        }
+    }
+
+    pub fn push(&self, at: u32, call: Call, outcome: Option<Token>) {
+        if self.len == 0 {
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.len {
+            events.pop_front();
+        }
+        events.push_back(Event { at, call, outcome });
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().iter().copied().collect()
+    }
+}
+
+/// Re-apply recorded calls to a fresh balancer, built with the same
+/// weights and options as the recorded one, and driven by `clock`.
+///
+/// Return the index of the first selection that differs
+/// from the recorded one, None if all of them match.
+pub fn replay<B>(events: &[Event], balancer: &B, clock: &MockClock) -> Option<usize>
+where
+    B: Balance<State = ()>,
+{
+    let mut diverged = None;
+
+    for (i, event) in events.iter().enumerate() {
+        clock.set(event.at);
+        match event.call {
+            Call::Next => {
+                if balancer.next(&()) != event.outcome && diverged.is_none() {
+                    diverged = Some(i);
+                }
+            }
+            Call::Disconnect(token) => balancer.on_disconnect(token),
+            Call::Success(token) => balancer.on_success(token),
+            Call::Failure(token) => balancer.on_failure(token),
+            Call::FailureClassified(token, class) => balancer.on_failure_classified(token, class),
+            Call::ReportedLoad(token, load) => balancer.on_reported_load(token, load),
+        }
+    }
+
+    diverged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::health::HealthConfig;
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn record_replay() {
+        fn build(clock: Arc<MockClock>) -> RoundRobin {
+            let health = HealthConfig {
+                max_fails: 2,
+                recovery_grace_secs: 5,
+                ..Default::default()
+            };
+            RoundRobin::with_health(&[5, 3, 1, 1], health).with_clock(clock)
+        }
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = build(clock.clone()).with_recording(1024);
+        for i in 0..200u32 {
+            if let Some(token) = rr.next(&()) {
+                match i % 7 {
+                    0 => rr.on_failure(token),
+                    3 => rr.on_failure_classified(token, FailureClass::Timeout),
+                    _ => rr.on_success(token),
+                }
+                rr.on_disconnect(token);
+            }
+            if i % 40 == 0 {
+                rr.on_reported_load(Token(1), 0.5);
+                clock.advance(3);
+            }
+        }
+
+        // nothing dropped
+        let events = rr.recorded();
+        assert!(events.len() < 1024);
+        assert!(events.iter().any(|x| x.outcome.is_some()));
+
+        let replay_clock = Arc::new(MockClock::new(0));
+        let fresh = build(replay_clock.clone());
+        assert_eq!(replay(&events, &fresh, &replay_clock), None);
+
+        assert_eq!(fresh.snapshot().nodes, rr.snapshot().nodes);
+        for _ in 0..50 {
+            assert_eq!(fresh.next(&()), rr.next(&()));
+        }
+
+        // a different pool diverges
+        let other = RoundRobin::new(&[1, 1, 1, 1]).with_clock(Arc::new(MockClock::new(0)));
+        assert!(replay(&events, &other, &MockClock::new(0)).is_some());
+    }
+
+    #[test]
+    fn record_bounded() {
+        let rr = RoundRobin::new(&[1, 1]).with_recording(3);
+        for _ in 0..5 {
+            rr.next(&());
+        }
+        rr.on_success(Token(0));
+
+        let events = rr.recorded();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].call, Call::Success(Token(0)));
+
+        let rr = RoundRobin::new(&[1, 1]);
+        rr.next(&());
+        assert!(rr.recorded().is_empty());
+    }
+}
@@ -1,151 +1,5185 @@
-use std::sync::Mutex;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::task::Poll;
+use std::time::Duration;
 
-use super::{Balance, Token};
+use smallvec::SmallVec;
+
+use super::{Balance, Strategy, Token};
+use crate::clock::{Clock, SystemClock};
+use crate::config::BalanceOptions;
+use crate::error::{BalanceError, Timeout};
+use crate::failure::{FailureAction, FailureClass};
+use crate::canary::Canary;
+use crate::fast::FastPath;
+use crate::history::{History, SelectionRecord};
+use crate::audit::{AuditEntry, AuditEvent, AuditLog};
+use crate::least_time::ewma;
+use crate::scored::NodeSignals;
+use crate::shed::{ShedConfig, Shedder};
+use crate::trickle::{spread_turn, Trickle};
+#[cfg(feature = "record")]
+use crate::record::{Call, Event, Recorder};
+use crate::notify::{Deadline, Hook, Notify};
+use crate::sample::{LogSampling, Sampler};
+#[cfg(feature = "lock-time")]
+use crate::histogram::Histogram;
+use crate::health::{EjectionReason, HealthConfig, NodeState, PeerHealth, PoolStatus};
+use crate::snapshot::{BalanceSnapshot, NodeDelta, NodeStat};
+use crate::spec::{DuplicatePolicy, NodeSpec};
+
+/// A reported load fades out linearly in this long.
+const LOAD_DECAY_SECS: u32 = 10;
+
+/// Max peers of a balancer, since a token is an u8 index.
+const MAX_PEERS: usize = u8::MAX as usize + 1;
+
+/// No last selection, since a token is an u8.
+const NO_TOKEN: u16 = u8::MAX as u16 + 1;
+
+/// The pool has not been degraded yet.
+const NEVER_DEGRADED: u32 = u32::MAX;
+
+/// The top-K set of `next_topk` is rebuilt at least this often.
+const TOPK_REFRESH_SECS: u32 = 1;
+
+/// What to do when all peers are draining.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DrainPolicy {
+    /// Select nothing, see [`RoundRobin::try_next`].
+    #[default]
+    Reject,
+    /// Select among draining peers as a last resort.
+    LastResort,
+}
+
+/// How a weight scaled to a fraction is rounded,
+/// see [`RoundRobin::with_rounding`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RoundingPolicy {
+    /// Round down, a small weight may be scaled to 0.
+    #[default]
+    Floor,
+    /// Round to the nearest.
+    Round,
+    /// Round up, a weight is never scaled to 0 by a factor above 0.
+    Ceil,
+}
+
+impl RoundingPolicy {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            RoundingPolicy::Floor => x.floor(),
+            RoundingPolicy::Round => x.round(),
+            // not up for float noise, e.g. of 10 * 0.3
+            RoundingPolicy::Ceil => (x - 1e-3).ceil(),
+        }
+    }
+}
+
+/// Put the worst peer on probation, see [`RoundRobin::with_probation`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbationPolicy {
+    /// A peer scored above this is worth probation, where the score is its
+    /// moving error rate, plus how much slower it is than the pool average,
+    /// e.g. 0.5 for 1.5 times slower, so an average peer scores 0.
+    pub threshold: f64,
+    /// How often peers are reviewed.
+    pub interval_secs: u32,
+}
+
+/// A selection, see [`RoundRobin::next_explained`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub token: Token,
+    /// The peer was out of rotation, and is let through
+    /// to probe whether it recovered.
+    pub recovery: bool,
+}
 
 /// Round-robin node.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Node {
-    cw: i16,
+    cw: i32,
+    // ramps up toward target
     ew: u8,
+    // configured, only changed by reconfiguration
     weight: u8,
+    // configured, scaled to the max cycle length, base of target
+    fitted: u8,
+    // adjusted at runtime, ceiling of ew
+    target: u8,
     token: Token,
+    key: Option<u64>,
+    addr: Option<SocketAddr>,
+    health: PeerHealth,
+    max_conns: u32,
+    // reported by the peer, fades out over time
+    load: u8,
+    load_at: u32,
+    // temporary multiplier of target, until when
+    boost: f32,
+    boost_until: u32,
+    tags: Vec<String>,
+    // lower is preferred
+    priority: u8,
+    // no new connections
+    draining: bool,
+    // reported unhealthy until when
+    sick_until: u32,
+    // evacuated since when, over how long
+    evacuate: Option<(u32, u32)>,
+    // of connection setup
+    connect_ewma: Option<f64>,
+    // of responses
+    latency_ewma: Option<f64>,
+    // of 0 on success, 1 on failure, only with a probation policy
+    error_ewma: Option<f64>,
+    // at a weight of 1, until its score improves
+    probation: bool,
+}
+
+impl Node {
+    fn new(token: Token, weight: u8) -> Self {
+        Node {
+            ew: weight,
+            cw: 0,
+            weight,
+            token,
+            key: None,
+            addr: None,
+            health: PeerHealth::default(),
+            max_conns: 0,
+            fitted: weight,
+            target: weight,
+            load: 0,
+            load_at: 0,
+            boost: 1.0,
+            boost_until: 0,
+            tags: Vec::new(),
+            priority: 0,
+            draining: false,
+            sick_until: 0,
+            evacuate: None,
+            connect_ewma: None,
+            latency_ewma: None,
+            error_ewma: None,
+            probation: false,
+        }
+    }
+
+    fn from_spec(token: Token, spec: &NodeSpec) -> Self {
+        Node {
+            addr: Some(spec.addr),
+            key: spec.key,
+            max_conns: spec.max_conns,
+            tags: spec.tags.clone(),
+            priority: spec.priority,
+            ..Node::new(token, spec.weight)
+        }
+    }
+
+    fn is_saturated(&self, conns: u32) -> bool {
+        self.max_conns != 0 && conns >= self.max_conns
+    }
+
+    /// Target with an unexpired boost.
+    fn boosted(&self, now: u32, rounding: RoundingPolicy) -> u8 {
+        if now < self.boost_until {
+            saturating_scale(self.target, self.boost, rounding)
+        } else {
+            self.target
+        }
+    }
+
+    /// Ceiling of ew, the boosted target lowered by the reported load.
+    fn ceiling(&self, now: u32, rounding: RoundingPolicy) -> u8 {
+        let target = self.boosted(now, rounding);
+        let left = LOAD_DECAY_SECS.saturating_sub(now.saturating_sub(self.load_at));
+        let load = self.load as u32 * left / LOAD_DECAY_SECS;
+        let ceiling = match rounding {
+            RoundingPolicy::Floor => target as u32 * (u8::MAX as u32 - load) / u8::MAX as u32,
+            _ => saturating_scale(target, (u8::MAX as u32 - load) as f32 / u8::MAX as f32, rounding) as u32,
+        };
+        let ceiling = ceiling.max(target.min(1) as u32);
+        let ceiling = match self.probation {
+            true => ceiling.min(1),
+            false => ceiling,
+        };
+
+        // ramps down to zero
+        match self.evacuate {
+            Some((at, secs)) => {
+                let left = secs.saturating_sub(now.saturating_sub(at));
+                ceiling.checked_mul(left).and_then(|x| x.checked_div(secs)).unwrap_or(0) as u8
+            }
+            None => ceiling as u8,
+        }
+    }
+
+    /// Drained, or done evacuating.
+    fn is_draining(&self, now: u32) -> bool {
+        self.draining || self.evacuate.is_some_and(|(at, secs)| now.saturating_sub(at) >= secs)
+    }
+}
+
+/// The lock is held by another thread.
+#[derive(Debug)]
+struct Contended;
+
+/// Peers of [`RoundRobin::next_topk`], with their own schedule.
+#[derive(Debug, Clone, Default)]
+struct TopK {
+    k: usize,
+    // built at, in generation
+    at: u32,
+    generation: u64,
+    tokens: Vec<Token>,
+    cw: Vec<i32>,
 }
 
 /// Round robin balancer.
 #[derive(Debug)]
 pub struct RoundRobin {
+    // indexed by token
     nodes: Mutex<Vec<Node>>,
-    total: u8,
+    // serializes structural changes, taken before `nodes`
+    // so that they are prepared without blocking selection
+    reconfig: Mutex<()>,
+    // no new selections at all, for shutdown
+    closed: AtomicBool,
+    // bumped by structural changes, with `nodes` held
+    generation: AtomicU64,
+    // start of the current degraded period, set when a peer
+    // goes out of rotation while every peer was in
+    degraded_since: AtomicU32,
+    // last selection, reused by try_next_now under contention
+    last: AtomicU16,
+    // micros `nodes` is held by a selection
+    #[cfg(feature = "lock-time")]
+    lock_time: Mutex<Histogram>,
+    total: AtomicU8,
+    health: Option<HealthConfig>,
+    clock: Arc<dyn Clock>,
+    released: Notify,
+    health_changed: Notify,
+    // indexed by token, outside of the lock
+    conns: Box<[AtomicU32]>,
+    // connections to come by token, counted with `conns` against `max_conns`
+    reserved: Box<[AtomicU32]>,
+    // selections by token, since the last topology swap
+    selections: Box<[AtomicU64]>,
+    // reported successes by token, since the last topology swap
+    successes: Box<[AtomicU64]>,
+    // schedule of simulate_next, and its selections by token
+    shadow: Mutex<Vec<i32>>,
+    // taken after `nodes`
+    topk: Mutex<TopK>,
+    simulated: Box<[AtomicU64]>,
+    // taken out of service by the operator, by token
+    disabled: Box<[AtomicBool]>,
+    // peers that ever succeeded, by token
+    trusted: Box<[AtomicBool]>,
+    // share of untrusted peers, 0 to trust at once
+    trust_fraction: f32,
+    trust_turns: AtomicU64,
+    // floor of each live peer, and the next one its turns go to
+    min_share: f32,
+    floor_turns: AtomicU64,
+    floor_next: AtomicUsize,
+    // half-open peers with a probe in flight, by token
+    probing: Box<[AtomicBool]>,
+    probe_hook: Option<Hook>,
+    drain_policy: DrainPolicy,
+    rounding: RoundingPolicy,
+    probation: Option<ProbationPolicy>,
+    // track the error rate even without probation, for scoring
+    error_tracking: bool,
+    // next review of probation
+    probation_at: AtomicU32,
+    sampler: Sampler,
+    history: History,
+    audit: AuditLog,
+    #[cfg(feature = "record")]
+    recorder: Recorder,
+    fast: FastPath,
+    canary: Canary,
+    shedder: Shedder,
+    trickle: Trickle,
+    snapshot_interval: u32,
+    start_offset: u8,
+    stagger_seed: Option<u64>,
+    max_cycle_length: u32,
+    min_healthy_fraction: f32,
+    // applied to specs again by topology swaps
+    duplicates: DuplicatePolicy,
+    // next request id of a traced selection
+    next_id: AtomicU64,
+    // metric labels by token, only read by snapshots
+    labels: RwLock<Vec<Vec<(String, String)>>>,
+    // published at, snapshot
+    published: RwLock<Option<(u32, Arc<BalanceSnapshot>)>>,
 }
 
 impl Balance for RoundRobin {
     type State = ();
 
     fn total(&self) -> u8 {
-        self.total
+        self.total.load(Ordering::Relaxed)
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::RoundRobin.name()
     }
 
     fn new(weights: &[u8]) -> Self {
         assert!(weights.len() <= u8::MAX as usize);
 
-        if weights.len() <= 1 {
-            return Self {
-                nodes: Mutex::new(Vec::new()),
-                total: weights.len() as u8,
-            };
+        if weights.len() > 1 && weights.iter().all(|w| *w == 0) {
+            log::warn!("[lb]all weights are zero, fallback to equal weights");
+            return Self::new(&vec![1; weights.len()]);
         }
 
         let nodes = weights
             .iter()
             .enumerate()
-            .map(|(i, w)| Node {
-                ew: *w,
-                cw: 0,
-                weight: *w,
-                token: Token(i as u8),
-            })
+            .map(|(i, w)| Node::new(Token(i as u8), *w))
             .collect();
+
         Self {
             nodes: Mutex::new(nodes),
-            total: weights.len() as u8,
+            reconfig: Mutex::new(()),
+            closed: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+            degraded_since: AtomicU32::new(NEVER_DEGRADED),
+            last: AtomicU16::new(NO_TOKEN),
+            #[cfg(feature = "lock-time")]
+            lock_time: Mutex::new(Histogram::default()),
+            total: AtomicU8::new(weights.len() as u8),
+            health: None,
+            clock: Arc::new(SystemClock),
+            released: Notify::default(),
+            health_changed: Notify::default(),
+            conns: (0..MAX_PEERS).map(|_| AtomicU32::new(0)).collect(),
+            reserved: (0..MAX_PEERS).map(|_| AtomicU32::new(0)).collect(),
+            selections: (0..MAX_PEERS).map(|_| AtomicU64::new(0)).collect(),
+            successes: (0..MAX_PEERS).map(|_| AtomicU64::new(0)).collect(),
+            shadow: Mutex::new(Vec::new()),
+            topk: Mutex::new(TopK::default()),
+            simulated: (0..MAX_PEERS).map(|_| AtomicU64::new(0)).collect(),
+            probing: (0..MAX_PEERS).map(|_| AtomicBool::new(false)).collect(),
+            disabled: (0..MAX_PEERS).map(|_| AtomicBool::new(false)).collect(),
+            trusted: (0..MAX_PEERS).map(|_| AtomicBool::new(false)).collect(),
+            trust_fraction: 0.0,
+            trust_turns: AtomicU64::new(0),
+            min_share: 0.0,
+            floor_turns: AtomicU64::new(0),
+            floor_next: AtomicUsize::new(0),
+            probe_hook: None,
+            drain_policy: DrainPolicy::default(),
+            rounding: RoundingPolicy::default(),
+            probation: None,
+            error_tracking: false,
+            probation_at: AtomicU32::new(0),
+            sampler: Sampler::default(),
+            history: History::default(),
+            audit: AuditLog::default(),
+            #[cfg(feature = "record")]
+            recorder: Recorder::default(),
+            fast: FastPath::default(),
+            canary: Canary::default(),
+            shedder: Shedder::default(),
+            trickle: Trickle::default(),
+            snapshot_interval: 0,
+            start_offset: 0,
+            stagger_seed: None,
+            max_cycle_length: 0,
+            min_healthy_fraction: 0.5,
+            duplicates: DuplicatePolicy::default(),
+            next_id: AtomicU64::new(1),
+            labels: RwLock::new(Vec::new()),
+            published: RwLock::new(None),
+        }
+    }
+
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        self.next_explained(state).map(|x| x.token)
+    }
+
+    fn on_disconnect(&self, token: Token) {
+        #[cfg(feature = "record")]
+        self.recorder.push(self.clock.now(), Call::Disconnect(token), None);
+        let _ = self.conns[token.0 as usize].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_sub(1));
+        self.probing[token.0 as usize].store(false, Ordering::Relaxed);
+        self.released.notify_all();
+    }
+
+    fn on_success(&self, token: Token) {
+        #[cfg(feature = "record")]
+        self.recorder.push(self.clock.now(), Call::Success(token), None);
+        self.shedder.on_outcome(self.clock.now(), true);
+        self.track_errors(token, false);
+        self.succeed(token);
+    }
+
+    fn on_failure(&self, token: Token) {
+        #[cfg(feature = "record")]
+        self.recorder.push(self.clock.now(), Call::Failure(token), None);
+        self.shedder.on_outcome(self.clock.now(), false);
+        self.track_errors(token, true);
+        self.fail(token, FailureAction::Count(1), EjectionReason::Failures(None));
+    }
+
+    fn on_failure_classified(&self, token: Token, class: FailureClass) {
+        #[cfg(feature = "record")]
+        self.recorder
+            .push(self.clock.now(), Call::FailureClassified(token, class), None);
+        self.shedder.on_outcome(self.clock.now(), false);
+        self.track_errors(token, true);
+        if let Some(health) = self.health {
+            let reason = EjectionReason::Failures(Some(class));
+            self.fail(token, health.failure_policy.get(class), reason);
+        }
+    }
+
+    fn on_reported_load(&self, token: Token, load: f32) {
+        #[cfg(feature = "record")]
+        self.recorder
+            .push(self.clock.now(), Call::ReportedLoad(token, load), None);
+        self.report_load(token, (load.clamp(0.0, 1.0) * u8::MAX as f32) as u8);
+    }
+
+    fn on_latency(&self, token: Token, micros: u64) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.latency_ewma = Some(ewma(p.latency_ewma, micros));
+        }
+    }
+
+    fn on_connect_time(&self, token: Token, micros: u64) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.connect_ewma = Some(ewma(p.connect_ewma, micros));
         }
     }
+}
 
+impl RoundRobin {
+    /// Err if the lock is contended and `wait` is false.
+    /// Tokens whose bit is set in `forbidden` are skipped.
     #[allow(clippy::significant_drop_in_scrutinee)]
-    fn next(&self, _: &Self::State) -> Option<Token> {
-        if self.total <= 1 {
-            return Some(Token(0));
+    fn select(&self, wait: bool, forbidden: u64) -> Result<Option<Selection>, Contended> {
+        // an empty pool, not a phantom peer
+        if self.total() == 0 || self.closed.load(Ordering::Relaxed) {
+            return Ok(None);
         }
 
         // lock the whole list
-        {
-            let mut nodes = self.nodes.lock().unwrap();
-            let mut tw: i16 = 0;
-            let mut best: Option<&mut Node> = None;
-            for p in nodes.iter_mut() {
-                tw += p.ew as i16;
-                p.cw += p.ew as i16;
-
-                if p.ew < p.weight {
-                    p.ew += 1;
-                }
+        let nodes = match self.nodes.try_lock() {
+            Ok(x) => x,
+            Err(TryLockError::WouldBlock) if wait => self.nodes.lock().unwrap(),
+            Err(TryLockError::WouldBlock) => return Err(Contended),
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        };
+        #[cfg(feature = "lock-time")]
+        let locked_at = std::time::Instant::now();
 
-                if let Some(ref x) = best {
-                    if p.cw > x.cw {
-                        best = Some(p);
-                    }
-                } else {
-                    best = Some(p);
+        let now = self.clock.now();
+
+        let allowed = |p: &Node| !is_masked(forbidden, p.token);
+        let canary = self.canary.token();
+        let canary_turn = canary.is_some() && self.canary.turn();
+
+        let mut probe = false;
+        let mut nodes = nodes;
+        let token = 'select: {
+            self.review_probation(&mut nodes, now);
+
+            if canary_turn {
+                let found = canary.and_then(|token| nodes.get_mut(token.0 as usize));
+                if let Some(p) = found.filter(|p| allowed(p) && !p.is_draining(now) && self.is_available(p, now)) {
+                    probe = self.admit(p, now);
+                    break 'select Some(p.token);
                 }
             }
 
-            best.map(|x| {
-                x.cw -= tw;
+            if let Some(p) = self.trickle_pick(&mut nodes, now, forbidden) {
+                self.probing[p.token.0 as usize].store(true, Ordering::Relaxed);
+                self.admit(p, now);
+                probe = true;
+                break 'select Some(p.token);
+            }
+
+            // the canary only gets its own share
+            let accept = |p: &Node| allowed(p) && Some(p.token) != canary && !p.is_draining(now);
+            if let Some(p) = self.floor_pick(&mut nodes, now, accept) {
+                probe = self.admit(p, now);
+                break 'select Some(p.token);
+            }
+            let mut picked = None;
+            if self.trust_fraction > 0.0 && spread_turn(&self.trust_turns, self.trust_fraction) {
+                picked = self.pick(&mut nodes, now, |p| accept(p) && !self.is_trusted(p.token));
+            }
+            if picked.is_none() {
+                picked = self.pick(&mut nodes, now, |p| accept(p) && self.is_trusted(p.token));
+            }
+            // none trusted yet
+            if picked.is_none() && self.trust_fraction > 0.0 {
+                picked = self.pick(&mut nodes, now, accept);
+            }
+            if picked.is_none() && self.drain_policy == DrainPolicy::LastResort {
+                picked = self.pick(&mut nodes, now, |p| allowed(p) && p.is_draining(now));
+            }
+            picked.map(|x| {
+                probe = self.admit(x, now);
                 x.token
             })
+        };
+        // reused under contention, so only what needs no lock to recheck
+        let reusable = token.filter(|t| !probe && nodes[t.0 as usize].max_conns == 0);
+        self.last
+            .store(reusable.map_or(NO_TOKEN, |t| t.0 as u16), Ordering::Relaxed);
+        drop(nodes);
+        #[cfg(feature = "lock-time")]
+        self.lock_time
+            .lock()
+            .unwrap()
+            .record(locked_at.elapsed().as_micros() as u64);
+
+        if let (true, Some(token), Some(hook)) = (probe, token, &self.probe_hook) {
+            hook.call(token);
+        }
+
+        if self.history.is_enabled() {
+            self.history.push(SelectionRecord {
+                at: now,
+                token,
+                healthy: self.healthy_count(),
+            });
+        }
+
+        if let Some(token) = token {
+            if self.sampler.hit(token) {
+                log::debug!("[lb]select peer, token: {:?}", token);
+            }
         }
+
+        Ok(token.map(|token| Selection { token, recovery: probe }))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use average::{Max, Mean, Min};
+    /// The last selected peer, if it can be selected again without
+    /// the lock, i.e. it is still in service.
+    fn reusable_last(&self) -> Option<Token> {
+        let token = match self.last.load(Ordering::Relaxed) {
+            NO_TOKEN => return None,
+            x => Token(x as u8),
+        };
+        let closed = self.closed.load(Ordering::Relaxed);
+        (!closed && self.is_known(token) && self.is_enabled(token)).then_some(token)
+    }
 
-    #[test]
-    fn rr_same_weight() {
-        let rr = RoundRobin::new(&vec![1; 255]);
-        let mut distro = [0f64; 255];
+    /// Stop [`try_next_now`](Self::try_next_now) reusing a peer
+    /// under contention, called with the peer list locked.
+    fn forget_last(&self, token: Token) {
+        let _ = self
+            .last
+            .compare_exchange(token.0 as u16, NO_TOKEN, Ordering::Relaxed, Ordering::Relaxed);
+    }
 
-        for _ in 0..1_000_000 {
-            let token = rr.next(&()).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+    /// Whether a peer could be selected, draining or not.
+    fn is_available(&self, p: &Node, now: u32) -> bool {
+        if !self.is_enabled(p.token) {
+            return false;
         }
 
-        let diffs: Vec<f64> = distro
-            .iter()
-            .map(|x| *x / 1_000_000.0 - 1.0 / 255.0)
-            .map(f64::abs)
-            .inspect(|x| assert!(x < &1e-3))
-            .collect();
+        let i = p.token.0 as usize;
+        let conns = self.conns[i].load(Ordering::Relaxed);
+        if p.is_saturated(conns.saturating_add(self.reserved[i].load(Ordering::Relaxed))) {
+            return false;
+        }
 
-        let min_diff: Min = diffs.iter().collect();
-        let max_diff: Max = diffs.iter().collect();
-        let mean_diff: Mean = diffs.iter().collect();
+        // one probe at a time
+        if let Some(health) = self.health_of(p) {
+            if p.health.is_half_open(&health, now) && self.probing[p.token.0 as usize].load(Ordering::Relaxed) {
+                return false;
+            }
+        }
 
-        println!("{:?}", distro);
-        println!("min diff: {}", min_diff.min());
-        println!("max diff: {}", max_diff.max());
-        println!("mean diff: {}", mean_diff.mean());
+        self.is_usable(p, now)
     }
 
-    #[test]
-    fn rr_all_weights() {
-        let weights: Vec<u8> = (1..=255).collect();
-        let total_weight: f64 = weights.iter().map(|x| *x as f64).sum();
-        let rr = RoundRobin::new(&weights);
-        let mut distro = [0f64; 255];
+    /// Whether a peer is in rotation and not disabled, regardless of its load.
+    fn is_in_service(&self, p: &Node, now: u32) -> bool {
+        self.is_enabled(p.token) && self.is_usable(p, now)
+    }
 
-        for _ in 0..1_000_000 {
-            let token = rr.next(&()).unwrap();
-            distro[token.0 as usize] += 1 as f64;
+    /// Whether a peer is in rotation, regardless of its load.
+    fn is_usable(&self, p: &Node, now: u32) -> bool {
+        if now < p.sick_until {
+            return false;
         }
 
-        let diffs: Vec<f64> = distro
+        match self.health_of(p) {
+            Some(health) => !p.health.is_ejected(&health, now),
+            None => true,
+        }
+    }
+
+    /// Health check config of a peer.
+    fn health_of(&self, p: &Node) -> Option<HealthConfig> {
+        self.health.map(|x| x.for_weight(p.weight))
+    }
+
+    /// The ejected peer closest to timing out, if this selection probes it.
+    fn trickle_pick<'a>(&self, nodes: &'a mut [Node], now: u32, forbidden: u64) -> Option<&'a mut Node> {
+        if !self.trickle.is_enabled() || self.health.is_none() {
+            return None;
+        }
+
+        let healthy = nodes.iter().filter(|p| self.is_usable(p, now)).count();
+        if !self.trickle.turn(healthy) {
+            return None;
+        }
+
+        nodes
+            .iter_mut()
+            .filter(|p| !p.is_draining(now) && self.is_enabled(p.token))
+            .filter(|p| !is_masked(forbidden, p.token))
+            .filter(|p| !self.probing[p.token.0 as usize].load(Ordering::Relaxed))
+            .filter_map(|p| {
+                let health = self.health_of(p)?;
+                let until = p.health.ejected?.saturating_add(p.health.timeout(&health));
+                p.health.is_ejected(&health, now).then_some((until, p))
+            })
+            .min_by_key(|(until, _)| *until)
+            .map(|(_, p)| p)
+    }
+
+    /// The next accepted peer in turn, if this selection
+    /// is one of the floor, see [`with_min_share`](Self::with_min_share).
+    fn floor_pick<'a>(&self, nodes: &'a mut [Node], now: u32, accept: impl Fn(&Node) -> bool) -> Option<&'a mut Node> {
+        if self.min_share == 0.0 {
+            return None;
+        }
+
+        let live = nodes.iter().filter(|p| accept(p) && self.is_available(p, now)).count();
+        if !spread_turn(&self.floor_turns, (self.min_share * live as f32).min(1.0)) {
+            return None;
+        }
+
+        let start = self.floor_next.load(Ordering::Relaxed) % nodes.len();
+        let i = (start..nodes.len())
+            .chain(0..start)
+            .find(|&i| accept(&nodes[i]) && self.is_available(&nodes[i], now))?;
+        self.floor_next.store(i + 1, Ordering::Relaxed);
+        Some(&mut nodes[i])
+    }
+
+    /// Smooth weighted pick among available peers that are accepted.
+    fn pick<'a>(&self, nodes: &'a mut [Node], now: u32, accept: impl Fn(&Node) -> bool) -> Option<&'a mut Node> {
+        // only the most preferred tier with an available peer
+        let tier = nodes
             .iter()
-            .enumerate()
-            .map(|(i, x)| *x / 1_000_000.0 - (i as f64 + 1.0) / total_weight)
-            .map(f64::abs)
-            .inspect(|x| assert!(x < &1e-3))
-            .collect();
+            .filter(|p| accept(p) && self.is_available(p, now))
+            .map(|p| p.priority)
+            .min()?;
 
-        let min_diff: Min = diffs.iter().collect();
-        let max_diff: Max = diffs.iter().collect();
-        let mean_diff: Mean = diffs.iter().collect();
+        let mut tw: i32 = 0;
+        let mut best: Option<&mut Node> = None;
+        for p in nodes.iter_mut() {
+            if p.priority != tier || !accept(p) || !self.is_available(p, now) {
+                continue;
+            }
 
-        println!("{:?}", distro);
-        println!("min diff: {}", min_diff.min());
-        println!("max diff: {}", max_diff.max());
-        println!("mean diff: {}", mean_diff.mean());
+            let ceiling = p.ceiling(now, self.rounding);
+            p.ew = p.ew.min(ceiling);
+
+            tw = tw.saturating_add(p.ew as i32);
+            p.cw = p.cw.saturating_add(p.ew as i32);
+
+            if p.ew < ceiling {
+                p.ew += 1;
+            }
+
+            if let Some(ref x) = best {
+                if p.cw > x.cw {
+                    best = Some(p);
+                }
+            } else {
+                best = Some(p);
+            }
+        }
+
+        let best = best?;
+        best.cw = best.cw.saturating_sub(tw);
+        Some(best)
+    }
+
+    /// Account a selected peer.
+    /// Return true if it is a probe of a half-open peer.
+    fn admit(&self, p: &mut Node, now: u32) -> bool {
+        let mut probe = false;
+        if let Some(health) = self.health_of(p) {
+            if p.health.is_half_open(&health, now) {
+                self.probing[p.token.0 as usize].store(true, Ordering::Relaxed);
+                probe = true;
+            }
+            p.health.on_selected(&health, now);
+        }
+        self.conns[p.token.0 as usize].fetch_add(1, Ordering::Relaxed);
+        self.selections[p.token.0 as usize].fetch_add(1, Ordering::Relaxed);
+        probe
+    }
+
+    /// Whether a token belongs to a current peer.
+    fn is_known(&self, token: Token) -> bool {
+        // tokens are always 0..total
+        token.0 < self.total()
+    }
+
+    /// Return false if the peer is unknown.
+    fn succeed(&self, token: Token) -> bool {
+        if self.is_known(token) {
+            self.trusted[token.0 as usize].store(true, Ordering::Relaxed);
+            self.successes[token.0 as usize].fetch_add(1, Ordering::Relaxed);
+        }
+
+        let Some(health) = self.health else {
+            return self.is_known(token);
+        };
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        let Some(p) = nodes.get_mut(token.0 as usize) else {
+            return false;
+        };
+        let was_ejected = p.health.ejected.is_some();
+        p.health.on_success(&health, now);
+        if was_ejected {
+            self.audit.push(now, Some(token), AuditEvent::Recovered);
+        }
+        self.probing[token.0 as usize].store(false, Ordering::Relaxed);
+        drop(nodes);
+        self.invalidate_snapshot();
+        self.health_changed.notify_all();
+        true
+    }
+
+    /// Return false if the peer is unknown.
+    fn fail(&self, token: Token, action: FailureAction, reason: EjectionReason) -> bool {
+        let Some(health) = self.health else {
+            return self.is_known(token);
+        };
+
+        if self.fast.get() == Some(token) {
+            self.fast.invalidate();
+        }
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        let Some(p) = nodes.get_mut(token.0 as usize) else {
+            return false;
+        };
+        self.forget_last(token);
+        let was_in_service = self.is_in_service(p, now);
+        let health = health.for_weight(p.weight);
+        let was_parked = p.health.parked;
+        self.probing[token.0 as usize].store(false, Ordering::Relaxed);
+        match action {
+            FailureAction::Ignore => return true,
+            FailureAction::Count(points) => {
+                if !p.health.on_failure(&health, now, points) {
+                    return true;
+                }
+            }
+            FailureAction::Eject => p.health.eject(&health, now),
+        }
+
+        if p.health.ejected == Some(now) {
+            p.health.reason = Some(reason);
+            self.audit.push(now, Some(token), AuditEvent::Ejected(reason));
+        }
+        if p.health.parked && p.health.ejected == Some(now) && !was_parked {
+            log::warn!(
+                "[lb]peer {} parked for {}s after {} ejections",
+                token.0,
+                p.health.timeout(&health),
+                p.health.ejections
+            );
+            self.audit.push(now, Some(token), AuditEvent::Parked);
+        }
+
+        // back off gradually, recovered by next()
+        let step = (p.fitted as u32).checked_div(health.max_fails).unwrap_or(0);
+        p.ew = p.ew.saturating_sub(step.min(u8::MAX as u32) as u8);
+        if was_in_service && !self.is_usable(p, now) {
+            self.mark_degraded(&nodes, token, now);
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+        self.health_changed.notify_all();
+        true
+    }
+
+    /// Same as [`on_success`](Balance::on_success),
+    /// return false if the peer is unknown, e.g. a stale token
+    /// from before a topology swap.
+    pub fn report_success(&self, token: Token) -> bool {
+        self.succeed(token)
+    }
+
+    /// Same as [`on_failure`](Balance::on_failure),
+    /// return false if the peer is unknown, e.g. a stale token
+    /// from before a topology swap.
+    pub fn report_failure(&self, token: Token) -> bool {
+        self.fail(token, FailureAction::Count(1), EjectionReason::Failures(None))
+    }
+
+    /// Same as [`report_success`](Self::report_success), but fail with
+    /// [`BalanceError::UnknownToken`] if the peer is unknown.
+    pub fn try_on_success(&self, token: Token) -> Result<(), BalanceError> {
+        match self.report_success(token) {
+            true => Ok(()),
+            false => Err(BalanceError::UnknownToken(token)),
+        }
+    }
+
+    /// Same as [`report_failure`](Self::report_failure), but fail with
+    /// [`BalanceError::UnknownToken`] if the peer is unknown.
+    pub fn try_on_failure(&self, token: Token) -> Result<(), BalanceError> {
+        match self.report_failure(token) {
+            true => Ok(()),
+            false => Err(BalanceError::UnknownToken(token)),
+        }
+    }
+
+    /// Constructor, reject all-zero weights instead of
+    /// falling back to equal weights.
+    pub fn try_new(weights: &[u8]) -> Result<Self, BalanceError> {
+        if !weights.is_empty() && weights.iter().all(|w| *w == 0) {
+            return Err(BalanceError::ZeroWeights);
+        }
+        Ok(Self::new(weights))
+    }
+
+    /// Constructor with passive health check.
+    ///
+    /// # Panics
+    ///
+    /// If `health` is invalid, see [`try_with_health`](Self::try_with_health).
+    pub fn with_health(weights: &[u8], health: HealthConfig) -> Self {
+        if let Err(e) = health.validate() {
+            panic!("{}", e);
+        }
+        Self {
+            health: Some(health),
+            ..Self::new(weights)
+        }
+    }
+
+    /// Constructor with passive health check,
+    /// fail if the config is invalid.
+    pub fn try_with_health(weights: &[u8], health: HealthConfig) -> Result<Self, BalanceError> {
+        health.validate()?;
+        Ok(Self::with_health(weights, health))
+    }
+
+    /// Constructor with all per-peer options,
+    /// and passive health check if `health` is given.
+    ///
+    /// # Panics
+    ///
+    /// If `health` is invalid, see [`HealthConfig::validate`].
+    pub fn from_specs(specs: &[NodeSpec], health: Option<HealthConfig>) -> Self {
+        Self::from_specs_with(specs, health, DuplicatePolicy::default())
+    }
+
+    /// Same as [`from_specs`](Self::from_specs), with specs
+    /// of the same address handled by `duplicates`.
+    pub fn from_specs_with(specs: &[NodeSpec], health: Option<HealthConfig>, duplicates: DuplicatePolicy) -> Self {
+        let specs = &duplicates.apply(specs);
+        if let Some(Err(e)) = health.map(|x| x.validate()) {
+            panic!("{}", e);
+        }
+        let weights: Vec<u8> = specs.iter().map(|x| x.weight).collect();
+        let rr = Self {
+            health,
+            duplicates,
+            ..Self::new(&weights)
+        };
+        {
+            let mut nodes = rr.nodes.lock().unwrap();
+            for (p, spec) in nodes.iter_mut().zip(specs) {
+                *p = Node {
+                    ew: p.ew,
+                    weight: p.weight,
+                    fitted: p.fitted,
+                    target: p.target,
+                    ..Node::from_spec(p.token, spec)
+                };
+            }
+        }
+        *rr.labels.write().unwrap() = specs.iter().map(|x| x.labels.clone()).collect();
+        rr
+    }
+
+    /// Use another time source.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Start at a point of the cycle drawn from `seed`, so that
+    /// balancers built at the same time with different seeds
+    /// do not all select the same peers first.
+    ///
+    /// Every cycle is still exact, the first one included.
+    pub fn with_stagger(self, seed: u64) -> Self {
+        let steps = splitmix64(seed) % (self.cycle_length().max(1) as u64);
+        Self {
+            stagger_seed: Some(seed),
+            ..self.skip(steps)
+        }
+    }
+
+    /// Scale weights down in proportion, with rounding, whenever their cycle
+    /// would be longer than `max` selections, see [`cycle_length`](Self::cycle_length).
+    /// 0 for no limit.
+    ///
+    /// Only selection uses the scaled weights, [`weights`](Self::weights)
+    /// still reports the configured ones, snapshots the scaled ones as targets.
+    ///
+    /// A nonzero weight is never scaled below 1, so the cycle may still
+    /// be longer with more peers than `max`.
+    pub fn with_max_cycle_length(self, max: u32) -> Self {
+        let rr = Self {
+            max_cycle_length: max,
+            ..self
+        };
+        {
+            let mut nodes = rr.nodes.lock().unwrap();
+            let weights: Vec<u8> = nodes.iter().map(|p| p.weight).collect();
+            let fitted = rr.fit_cycle(&weights);
+            for (p, w) in nodes.iter_mut().zip(fitted) {
+                (p.fitted, p.target, p.ew) = (w, w, w);
+            }
+        }
+        rr
+    }
+
+    /// Start `offset` selections into the cycle, so that instances
+    /// over the same peers given different offsets prefer different
+    /// first picks. Wraps around the cycle length.
+    pub fn with_start_offset(self, offset: u8) -> Self {
+        let steps = offset as u64 % (self.cycle_length().max(1) as u64);
+        Self {
+            start_offset: offset,
+            ..self.skip(steps)
+        }
+    }
+
+    // run `steps` selections on the fitted weights
+    fn skip(self, steps: u64) -> Self {
+        {
+            let mut nodes = self.nodes.lock().unwrap();
+            let tw: i32 = nodes.iter().map(|p| p.fitted as i32).sum();
+            for _ in 0..steps {
+                let mut best: Option<&mut Node> = None;
+                for p in nodes.iter_mut() {
+                    p.cw += p.fitted as i32;
+                    match best {
+                        Some(ref x) if p.cw <= x.cw => {}
+                        _ => best = Some(p),
+                    }
+                }
+                if let Some(x) = best {
+                    x.cw -= tw;
+                }
+            }
+        }
+        self
+    }
+
+    /// Reuse a published snapshot for up to `secs`, so that
+    /// monitoring reads do not contend with [`next`](Balance::next).
+    /// The snapshot is republished early on a health or weight change.
+    pub fn with_snapshot_interval(self, secs: u32) -> Self {
+        Self {
+            snapshot_interval: secs,
+            ..self
+        }
+    }
+
+    /// Log sampled selections.
+    pub fn with_log_sampling(self, mode: LogSampling) -> Self {
+        Self {
+            sampler: Sampler::new(mode),
+            ..self
+        }
+    }
+
+    /// Keep the last `len` selections, see [`recent_selections`](Self::recent_selections).
+    pub fn with_selection_history(self, len: usize) -> Self {
+        Self {
+            history: History::new(len),
+            ..self
+        }
+    }
+
+    /// Record the last `len` calls, see [`recorded`](Self::recorded).
+    #[cfg(feature = "record")]
+    pub fn with_recording(self, len: usize) -> Self {
+        Self {
+            recorder: Recorder::new(len),
+            ..self
+        }
+    }
+
+    /// Call `hook` when a half-open peer is let through to probe it.
+    ///
+    /// Only one probe of a peer is in flight at a time, other selections
+    /// skip the peer until the probe is reported or disconnected.
+    pub fn with_probe_hook(self, hook: impl Fn(Token) + Send + Sync + 'static) -> Self {
+        Self {
+            probe_hook: Some(Hook::new(hook)),
+            ..self
+        }
+    }
+
+    /// What to do when all peers are draining.
+    pub fn with_drain_policy(self, policy: DrainPolicy) -> Self {
+        Self {
+            drain_policy: policy,
+            ..self
+        }
+    }
+
+    /// Below this fraction of peers not ejected, the pool is
+    /// [`Down`](PoolStatus::Down), see [`pool_status`](Self::pool_status).
+    /// Defaults to 0.5.
+    pub fn with_min_healthy_fraction(self, fraction: f32) -> Self {
+        Self {
+            min_healthy_fraction: fraction.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Shed selections while the pool as a whole is failing.
+    pub fn with_shedding(self, config: ShedConfig) -> Self {
+        Self {
+            shedder: Shedder::new(config),
+            ..self
+        }
+    }
+
+    /// While fewer than `below_healthy` peers are healthy, send `fraction`
+    /// of the selections to the ejected peer closest to timing out, rather
+    /// than all of them to the survivors. Only one such probe of a peer is
+    /// in flight at a time, like a half-open one.
+    ///
+    /// A success recovers the peer at once, a failure ejects it again.
+    pub fn with_trickle(self, below_healthy: u8, fraction: f32) -> Self {
+        Self {
+            trickle: Trickle::new(below_healthy, fraction),
+            ..self
+        }
+    }
+
+    /// Until a peer first succeeds, only offer it `fraction` of the
+    /// selections, shared with the other peers not trusted yet, so that
+    /// a peer dead on arrival fails little traffic. While no peer is
+    /// trusted, e.g. right after construction, peers are selected as usual.
+    pub fn with_probe_before_trust(self, fraction: f32) -> Self {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        Self {
+            trust_fraction: fraction,
+            ..self
+        }
+    }
+
+    /// Guarantee every live peer at least `fraction` of the selections,
+    /// whatever its weight, so that a light peer among heavy ones is not
+    /// starved for long stretches. Floor selections go to the peers in
+    /// turn, the rest by weight.
+    ///
+    /// Past `1 / peers`, all selections go in turn.
+    pub fn with_min_share(self, fraction: f32) -> Self {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        Self {
+            min_share: fraction,
+            ..self
+        }
+    }
+
+    /// Whether a peer ever succeeded, see [`with_probe_before_trust`](Self::with_probe_before_trust).
+    /// Always true without it.
+    pub fn is_trusted(&self, token: Token) -> bool {
+        self.trust_fraction == 0.0 || self.trusted[token.0 as usize].load(Ordering::Relaxed)
+    }
+
+    /// Whether selections are being shed, see [`with_shedding`](Self::with_shedding).
+    pub fn is_shedding(&self) -> bool {
+        self.shedder.is_active(self.clock.now())
+    }
+
+    /// How a weight scaled by [`scale_weight`](Self::scale_weight),
+    /// [`boost`](Self::boost) or [`report_load`](Self::report_load) is rounded.
+    ///
+    /// With [`RoundingPolicy::Ceil`] a small peer is never shed out of
+    /// rotation entirely. Reported load and [`with_max_cycle_length`](Self::with_max_cycle_length)
+    /// keep a peer at 1 regardless.
+    pub fn with_rounding(self, rounding: RoundingPolicy) -> Self {
+        Self { rounding, ..self }
+    }
+
+    /// Move the worst peer to probation, at a weight of 1, while its
+    /// score is above the threshold, so it keeps being validated by a
+    /// minimal share of traffic, without hurting users. It is restored
+    /// once its score improves, and ramps up like a recovery.
+    ///
+    /// Peers are reviewed by selections, at most once per interval,
+    /// and at most one more is put on probation each time.
+    pub fn with_probation(self, policy: ProbationPolicy) -> Self {
+        Self {
+            probation: Some(policy),
+            ..self
+        }
+    }
+
+    /// Track the error rate of every peer, for [`ScoredBalancer`](crate::scored::ScoredBalancer).
+    pub(crate) fn with_error_tracking(self) -> Self {
+        Self {
+            error_tracking: true,
+            ..self
+        }
+    }
+
+    /// Add passive health check to a built balancer, for [`ScoredBalancer`](crate::scored::ScoredBalancer).
+    pub(crate) fn with_health_config(self, health: HealthConfig) -> Self {
+        Self {
+            health: Some(health),
+            ..self
+        }
+    }
+
+    /// Signals of all peers, see [`ScoredBalancer`](crate::scored::ScoredBalancer).
+    pub(crate) fn signals(&self) -> Vec<NodeSignals> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| self.signals_of(p)).collect()
+    }
+
+    fn signals_of(&self, p: &Node) -> NodeSignals {
+        NodeSignals {
+            token: p.token,
+            weight: p.weight,
+            active_conns: self.active_conns(p.token),
+            latency_micros: p.latency_ewma,
+            error_rate: p.error_ewma.unwrap_or(0.0),
+        }
+    }
+
+    /// Select the available peer with the lowest score of its signals,
+    /// instead of by weights, for [`ScoredBalancer`](crate::scored::ScoredBalancer).
+    ///
+    /// Peers with a zero weight or a NaN score are skipped.
+    pub(crate) fn select_scored(&self, scorer: &dyn Fn(&NodeSignals) -> f64) -> Option<Token> {
+        if self.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut best: Option<(f64, &mut Node)> = None;
+        for p in nodes.iter_mut() {
+            if p.weight == 0 || p.is_draining(now) || !self.is_available(p, now) {
+                continue;
+            }
+            let score = scorer(&self.signals_of(p));
+            match best {
+                _ if score.is_nan() => {}
+                Some((x, _)) if score >= x => {}
+                _ => best = Some((score, p)),
+            }
+        }
+
+        let (_, p) = best?;
+        self.admit(p, now);
+        Some(p.token)
+    }
+
+    /// Whether a peer is on probation, see [`with_probation`](Self::with_probation).
+    pub fn is_on_probation(&self, token: Token) -> bool {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).is_some_and(|p| p.probation)
+    }
+
+    fn track_errors(&self, token: Token, failed: bool) {
+        if self.probation.is_none() && !self.error_tracking {
+            return;
+        }
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.error_ewma = Some(ewma(p.error_ewma, failed as u64));
+        }
+    }
+
+    fn review_probation(&self, nodes: &mut [Node], now: u32) {
+        let Some(policy) = self.probation else {
+            return;
+        };
+        // under the lock
+        if now < self.probation_at.load(Ordering::Relaxed) {
+            return;
+        }
+        self.probation_at
+            .store(now.saturating_add(policy.interval_secs.max(1)), Ordering::Relaxed);
+
+        let latencies: Vec<f64> = nodes.iter().filter_map(|p| p.latency_ewma).collect();
+        let mean = (!latencies.is_empty()).then(|| latencies.iter().sum::<f64>() / latencies.len() as f64);
+        let scores: Vec<f64> = nodes
+            .iter()
+            .map(|p| {
+                let slowness = match (p.latency_ewma, mean) {
+                    (Some(x), Some(mean)) if mean > 0.0 => (x / mean - 1.0).max(0.0),
+                    _ => 0.0,
+                };
+                p.error_ewma.unwrap_or(0.0) + slowness
+            })
+            .collect();
+
+        for (p, score) in nodes.iter_mut().zip(&scores) {
+            if p.probation && *score <= policy.threshold {
+                log::info!("[lb]peer {} off probation, score: {:.2}", p.token.0, score);
+                p.probation = false;
+            }
+        }
+
+        if nodes.len() < 2 {
+            return;
+        }
+        let worst = (0..nodes.len())
+            .filter(|i| self.is_usable(&nodes[*i], now))
+            .max_by(|a, b| scores[*a].total_cmp(&scores[*b]));
+        if let Some(i) = worst.filter(|i| scores[*i] > policy.threshold && !nodes[*i].probation) {
+            log::warn!("[lb]peer {} on probation, score: {:.2}", i, scores[i]);
+            nodes[i].probation = true;
+            nodes[i].ew = nodes[i].ew.min(1);
+        }
+    }
+
+    /// Keep up to `len` last changes of weights, health, and
+    /// service state, see [`audit_log`](Self::audit_log).
+    pub fn with_audit_log(self, len: usize) -> Self {
+        Self {
+            audit: AuditLog::new(len),
+            ..self
+        }
+    }
+
+    /// Get the last changes, oldest first.
+    ///
+    /// Always empty unless built [`with_audit_log`](Self::with_audit_log).
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.audit.entries()
+    }
+
+    /// Apply all options at once.
+    pub fn with_options(self, options: BalanceOptions) -> Self {
+        let rr = self
+            .with_snapshot_interval(options.snapshot_interval_secs)
+            .with_selection_history(options.selection_history)
+            .with_drain_policy(options.drain_policy)
+            .with_rounding(options.rounding)
+            .with_max_cycle_length(options.max_cycle_length)
+            .with_start_offset(options.start_offset);
+        match options.stagger_seed {
+            Some(seed) => rr.with_stagger(seed),
+            None => rr,
+        }
+    }
+
+    /// Get options in use.
+    pub fn options(&self) -> BalanceOptions {
+        BalanceOptions {
+            snapshot_interval_secs: self.snapshot_interval,
+            selection_history: self.history.len(),
+            drain_policy: self.drain_policy,
+            rounding: self.rounding,
+            start_offset: self.start_offset,
+            stagger_seed: self.stagger_seed,
+            max_cycle_length: self.max_cycle_length,
+        }
+    }
+
+    /// Get the passive health check config.
+    pub fn health_config(&self) -> Option<HealthConfig> {
+        self.health
+    }
+
+    /// Let [`next_fast`](Self::next_fast) reuse the last selection
+    /// for calls within `window`.
+    pub fn with_fast_window(self, window: Duration) -> Self {
+        Self {
+            fast: FastPath::new(window),
+            ..self
+        }
+    }
+
+    /// Select next peer, without taking the lock if a selection
+    /// was made within the fast window.
+    ///
+    /// Bursts are sent to the same peer, trading a little
+    /// fairness for throughput. Same as [`next`](Balance::next)
+    /// if the window is not set.
+    ///
+    /// The peer is only reused as long as [`try_next_now`](Self::try_next_now)
+    /// could reuse it, e.g. not once it is drained, capped or removed.
+    pub fn next_fast(&self, state: &()) -> Option<Token> {
+        if !self.fast.is_enabled() {
+            return self.next(state);
+        }
+
+        if let Some(token) = self.fast.get().filter(|x| self.reusable_last() == Some(*x)) {
+            self.conns[token.0 as usize].fetch_add(1, Ordering::Relaxed);
+            self.selections[token.0 as usize].fetch_add(1, Ordering::Relaxed);
+            return Some(token);
+        }
+
+        let token = self.next(state)?;
+        self.fast.put(token);
+        Some(token)
+    }
+
+    /// Get peers whose ejection has timed out, but have not succeeded yet.
+    pub fn half_open(&self) -> Vec<Token> {
+        if self.health.is_none() {
+            return Vec::new();
+        }
+
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .filter(|p| self.health_of(p).is_some_and(|x| p.health.is_half_open(&x, now)))
+            .map(|p| p.token)
+            .collect()
+    }
+
+    /// Whether a peer is in service: not disabled, ejected
+    /// nor reported unhealthy. Unknown peers are never healthy.
+    ///
+    /// A draining peer is still healthy, as its open connections
+    /// are fine to reuse, it only takes no new ones.
+    pub fn is_healthy(&self, token: Token) -> bool {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).is_some_and(|p| self.is_in_service(p, now))
+    }
+
+    /// Whether a peer is still warming up, i.e. its effective weight
+    /// is below its weight, as scaled and lowered by reported load.
+    pub fn is_ramping(&self, token: Token) -> bool {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .get(token.0 as usize)
+            .is_some_and(|p| p.ew < p.ceiling(now, self.rounding))
+    }
+
+    /// When an ejected peer is let back in, half-open, None if it is not ejected.
+    pub fn ejected_until(&self, token: Token) -> Option<u32> {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        let p = nodes.get(token.0 as usize)?;
+        let health = self.health_of(p)?;
+        if !p.health.is_ejected(&health, now) {
+            return None;
+        }
+        p.health.ejected.map(|at| at.saturating_add(p.health.timeout(&health)))
+    }
+
+    /// Whether a peer is parked after too many ejections in a row,
+    /// see [`HealthConfig`].
+    pub fn is_parked(&self, token: Token) -> bool {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).is_some_and(|p| p.health.parked)
+    }
+
+    /// Why a peer is ejected, None if it is not.
+    ///
+    /// Kept while the peer is half-open, until it succeeds.
+    pub fn ejection_reason(&self, token: Token) -> Option<EjectionReason> {
+        let nodes = self.nodes.lock().unwrap();
+        let p = nodes.get(token.0 as usize)?;
+        if self.clock.now() < p.sick_until {
+            return Some(EjectionReason::Reported);
+        }
+        p.health.ejected.and(p.health.reason)
+    }
+
+    /// Take a peer out of rotation for `ttl_secs`, e.g. when it answers
+    /// but reports itself degraded. Failures are not counted.
+    ///
+    /// A later report replaces the previous one.
+    pub fn report_unhealthy(&self, token: Token, ttl_secs: u32) {
+        if self.fast.get() == Some(token) {
+            self.fast.invalidate();
+        }
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            let was_in_service = self.is_in_service(p, now);
+            p.sick_until = now.saturating_add(ttl_secs);
+            self.forget_last(token);
+            self.audit
+                .push(now, Some(token), AuditEvent::Ejected(EjectionReason::Reported));
+            if was_in_service && !self.is_usable(p, now) {
+                self.mark_degraded(&nodes, token, now);
+            }
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+        self.health_changed.notify_all();
+    }
+
+    /// An active health check probe of a peer failed.
+    pub(crate) fn on_probe_failure(&self, token: Token) {
+        self.fail(token, FailureAction::Count(1), EjectionReason::ProbeFailed);
+    }
+
+    /// Stop sending new connections to a peer,
+    /// existing ones are left alone.
+    pub fn drain(&self, token: Token) {
+        self.set_draining(token, true);
+    }
+
+    /// Send new connections to a drained peer again.
+    pub fn undrain(&self, token: Token) {
+        self.set_draining(token, false);
+    }
+
+    fn set_draining(&self, token: Token, draining: bool) {
+        if self.fast.get() == Some(token) {
+            self.fast.invalidate();
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.draining = draining;
+            p.evacuate = None;
+            self.forget_last(token);
+            let event = match draining {
+                true => AuditEvent::Drained,
+                false => AuditEvent::Undrained,
+            };
+            self.audit.push(self.clock.now(), Some(token), event);
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Take a peer out of service until it is [`enable`](Self::enable)d,
+    /// e.g. for maintenance.
+    ///
+    /// Unlike [`drain`](Self::drain) or [`report_unhealthy`](Self::report_unhealthy),
+    /// the peer keeps its weight and health state meanwhile.
+    pub fn disable(&self, token: Token) {
+        if !self.is_known(token) {
+            return;
+        }
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        if nodes.get(token.0 as usize).is_some_and(|p| self.is_in_service(p, now)) {
+            self.mark_degraded(&nodes, token, now);
+        }
+        self.disabled[token.0 as usize].store(true, Ordering::Relaxed);
+        drop(nodes);
+        self.audit.push(now, Some(token), AuditEvent::Disabled);
+        if self.fast.get() == Some(token) {
+            self.fast.invalidate();
+        }
+        self.invalidate_snapshot();
+    }
+
+    /// Put a disabled peer back in service.
+    pub fn enable(&self, token: Token) {
+        if self.is_known(token) {
+            self.disabled[token.0 as usize].store(false, Ordering::Relaxed);
+            self.audit.push(self.clock.now(), Some(token), AuditEvent::Enabled);
+            self.invalidate_snapshot();
+            self.health_changed.notify_all();
+        }
+    }
+
+    /// Whether a peer is in service, see [`disable`](Self::disable).
+    pub fn is_enabled(&self, token: Token) -> bool {
+        !self.disabled[token.0 as usize].load(Ordering::Relaxed)
+    }
+
+    /// Stop all new selections for a graceful shutdown, existing
+    /// connections are left to finish, see [`await_drained`](Self::await_drained).
+    ///
+    /// Every peer is drained, and unlike draining them one by one,
+    /// [`DrainPolicy::LastResort`] does not apply.
+    pub fn drain_all(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.fast.invalidate();
+
+        let mut nodes = self.nodes.lock().unwrap();
+        for p in nodes.iter_mut() {
+            p.draining = true;
+            p.evacuate = None;
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Send new connections to all peers again, after [`drain_all`](Self::drain_all).
+    pub fn undrain_all(&self) {
+        let mut nodes = self.nodes.lock().unwrap();
+        for p in nodes.iter_mut() {
+            p.draining = false;
+            p.evacuate = None;
+        }
+        drop(nodes);
+        self.closed.store(false, Ordering::Relaxed);
+        self.invalidate_snapshot();
+    }
+
+    /// Wait up to `timeout` until there are no active connections
+    /// left, usually after [`drain_all`](Self::drain_all).
+    pub async fn await_drained(&self, timeout: Duration) -> Result<(), Timeout> {
+        let mut deadline = Deadline::after(timeout);
+        std::future::poll_fn(|cx| {
+            self.released.register(cx.waker());
+            if self.total_active_conns() == 0 {
+                Poll::Ready(Ok(()))
+            } else if deadline.poll_expired(cx) {
+                Poll::Ready(Err(Timeout))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Shift the share of a peer to the others over `over_secs`,
+    /// rather than at once, then drain it. The peer can then be
+    /// removed with [`swap_topology`](Self::swap_topology) once
+    /// its connections are gone.
+    ///
+    /// [`undrain`](Self::undrain) cancels it.
+    pub fn evacuate(&self, token: Token, over_secs: u32) {
+        if self.fast.get() == Some(token) {
+            self.fast.invalidate();
+        }
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.evacuate = Some((now, over_secs));
+            self.forget_last(token);
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Whether all peers are draining.
+    pub fn is_all_draining(&self) -> bool {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        !nodes.is_empty() && nodes.iter().all(|p| p.is_draining(now))
+    }
+
+    /// Same as [`next`](Balance::next), but tell why nothing is selected.
+    ///
+    /// Fails with [`BalanceError::Draining`] if all peers are draining,
+    /// unless the drain policy allows a last resort.
+    pub fn try_next(&self, state: &()) -> Result<Token, BalanceError> {
+        if let Some(token) = self.next(state) {
+            return Ok(token);
+        }
+
+        if self.is_all_draining() {
+            return Err(BalanceError::Draining);
+        }
+
+        Err(BalanceError::NotEnoughPeers {
+            wanted: 1,
+            available: 0,
+        })
+    }
+
+    /// Same as [`next`](Balance::next), but also tell whether the peer is
+    /// let through to probe its recovery, e.g. to correlate with the outcome.
+    pub fn next_explained(&self, _: &()) -> Option<Selection> {
+        self.next_with(true).ok().flatten()
+    }
+
+    /// Same as [`next`](Balance::next), but never wait for the lock.
+    ///
+    /// If another thread holds it, the last selected peer is selected
+    /// again, trading fairness for bounded latency. That is only if it
+    /// was not a recovery probe, has no `max_conns`, is still enabled, and
+    /// was not drained, failed or reported unhealthy since. None otherwise,
+    /// or if it is contended before anything was selected.
+    pub fn try_next_now(&self, _: &()) -> Option<Token> {
+        match self.next_with(false) {
+            Ok(x) => x.map(|x| x.token),
+            Err(Contended) => {
+                let token = self.reusable_last()?;
+                self.conns[token.0 as usize].fetch_add(1, Ordering::Relaxed);
+                self.selections[token.0 as usize].fetch_add(1, Ordering::Relaxed);
+                Some(token)
+            }
+        }
+    }
+
+    fn next_with(&self, wait: bool) -> Result<Option<Selection>, Contended> {
+        let selection = match self.shedder.shed(self.clock.now()) {
+            true => None,
+            false => self.select(wait, 0)?,
+        };
+        #[cfg(feature = "record")]
+        self.recorder
+            .push(self.clock.now(), Call::Next, selection.map(|x| x.token));
+        Ok(selection)
+    }
+
+    /// Same as [`next`](Balance::next), but skip the peers whose bit is set
+    /// in `forbidden`, e.g. incompatible with this request. Peers past the
+    /// 64th cannot be masked.
+    pub fn next_masked(&self, _: &(), forbidden: u64) -> Option<Token> {
+        if self.shedder.shed(self.clock.now()) {
+            return None;
+        }
+        self.select(true, forbidden).ok().flatten().map(|x| x.token)
+    }
+
+    /// Get next peer, along with the [`generation`](Self::generation)
+    /// it was selected in.
+    ///
+    /// If the generation changed by the time the peer is used, the
+    /// selection may be stale, e.g. its token now names another peer.
+    pub fn next_with_generation(&self, state: &()) -> Option<(Token, u64)> {
+        // read first, a change in between only makes it look stale
+        let generation = self.generation();
+        let token = self.next(state)?;
+        Some((token, generation))
+    }
+
+    /// Select among the `k` available peers of the highest
+    /// capacity only, by their weights, for very large pools.
+    ///
+    /// The set is kept between calls, and rebuilt once a member is no
+    /// longer available or every second, so a selection
+    /// costs O(k) rather than O(n), while peers just recovered or ramped
+    /// up wait for the next rebuild. The schedule is separate from
+    /// [`next`](Balance::next), which should not be mixed with this.
+    pub fn next_topk(&self, _: &(), k: usize) -> Option<Token> {
+        if k == 0 || self.total() == 0 || self.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let now = self.clock.now();
+        if self.shedder.shed(now) {
+            return None;
+        }
+
+        let generation = self.generation();
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut topk = self.topk.lock().unwrap();
+        let accept = |p: &Node| !p.is_draining(now) && self.is_available(p, now);
+
+        let stale = topk.k != k
+            || topk.generation != generation
+            || now.saturating_sub(topk.at) >= TOPK_REFRESH_SECS
+            || !topk.tokens.iter().all(|x| accept(&nodes[x.0 as usize]));
+        if stale {
+            let mut ranked: Vec<&Node> = nodes.iter().filter(|p| accept(p)).collect();
+            ranked.sort_by_key(|p| std::cmp::Reverse(p.ceiling(now, self.rounding)));
+            ranked.truncate(k);
+            let tokens: Vec<Token> = ranked.iter().map(|p| p.token).collect();
+            *topk = TopK {
+                k,
+                at: now,
+                generation,
+                cw: vec![0; tokens.len()],
+                tokens,
+            };
+        }
+
+        // same as pick, over the set
+        let mut tw: i32 = 0;
+        let mut best: Option<usize> = None;
+        for i in 0..topk.tokens.len() {
+            let p = &mut nodes[topk.tokens[i].0 as usize];
+            let ceiling = p.ceiling(now, self.rounding);
+            p.ew = p.ew.min(ceiling);
+
+            tw = tw.saturating_add(p.ew as i32);
+            topk.cw[i] = topk.cw[i].saturating_add(p.ew as i32);
+
+            if p.ew < ceiling {
+                p.ew += 1;
+            }
+
+            if best.is_none_or(|x| topk.cw[i] > topk.cw[x]) {
+                best = Some(i);
+            }
+        }
+
+        let best = best?;
+        topk.cw[best] = topk.cw[best].saturating_sub(tw);
+        let p = &mut nodes[topk.tokens[best].0 as usize];
+        self.admit(p, now);
+        Some(p.token)
+    }
+
+    /// Get the number of structural changes so far,
+    /// by [`swap_topology`](Self::swap_topology) or a weight change.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Get next peer, along with a unique request id to correlate
+    /// the later reports of this connection with.
+    ///
+    /// Ids start from 1 and increase with each selection.
+    pub fn next_traced(&self, state: &()) -> Option<(Token, u64)> {
+        let token = self.next(state)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        log::trace!("[lb]request {} select peer, token: {:?}", id, token);
+        Some((token, id))
+    }
+
+    /// Get the number of selections after which
+    /// every peer got exactly its share.
+    pub fn cycle_length(&self) -> u32 {
+        let nodes = self.nodes.lock().unwrap();
+        cycle_of(nodes.iter().map(|p| p.fitted))
+    }
+
+    /// Scale `weights` down to fit the max cycle length, if any.
+    fn fit_cycle(&self, weights: &[u8]) -> Vec<u8> {
+        let max = self.max_cycle_length;
+        let cycle = cycle_of(weights.iter().copied());
+        if max == 0 || cycle <= max {
+            return weights.to_vec();
+        }
+
+        let sum: u32 = weights.iter().map(|w| *w as u32).sum();
+        let mut fitted = weights.to_vec();
+        for target in (1..=max).rev() {
+            fitted = weights
+                .iter()
+                .map(|w| match *w as u32 {
+                    0 => 0,
+                    w => ((w * target + sum / 2) / sum).max(1) as u8,
+                })
+                .collect();
+            if cycle_of(fitted.iter().copied()) <= max {
+                break;
+            }
+        }
+
+        log::warn!(
+            "[lb]cycle length {} exceeds {}, weights scaled down to {:?}",
+            cycle,
+            max,
+            fitted
+        );
+        fitted
+    }
+
+    /// Get the tags of a peer.
+    pub fn tags(&self, token: Token) -> Vec<String> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).map_or_else(Vec::new, |p| p.tags.clone())
+    }
+
+    /// Get the tier of a peer.
+    pub fn priority(&self, token: Token) -> Option<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).map(|p| p.priority)
+    }
+
+    /// Get the address of a peer.
+    pub fn addr(&self, token: Token) -> Option<SocketAddr> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).and_then(|p| p.addr)
+    }
+
+    /// Get the current token of a peer by its stable key.
+    pub fn token_for_key(&self, key: u64) -> Option<Token> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().find(|p| p.key == Some(key)).map(|p| p.token)
+    }
+
+    /// Get the stable key of a peer.
+    pub fn key_for_token(&self, token: Token) -> Option<u64> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(token.0 as usize).and_then(|p| p.key)
+    }
+
+    /// Get active connections of a peer.
+    pub fn active_conns(&self, token: Token) -> u32 {
+        self.conns[token.0 as usize].load(Ordering::Relaxed)
+    }
+
+    /// Get active connections of all peers.
+    pub fn total_active_conns(&self) -> u64 {
+        self.conns[..self.total() as usize]
+            .iter()
+            .map(|x| x.load(Ordering::Relaxed) as u64)
+            .sum()
+    }
+
+    /// Get active connections of all peers.
+    pub fn connections(&self) -> Vec<(Token, u32)> {
+        // hold the lock, so that the list is not torn by a topology swap
+        let _nodes = self.nodes.lock().unwrap();
+        (0..self.total())
+            .map(|i| (Token(i), self.active_conns(Token(i))))
+            .collect()
+    }
+
+    /// Get the peer [`next`](Balance::next) would select, on a schedule of
+    /// its own, and count it, see [`simulated`](Self::simulated).
+    ///
+    /// The live schedule is not touched and no connection is counted, so
+    /// it is safe to call on live traffic. Draining, health and priority
+    /// are honored, canary, trickle and shedding turns are not.
+    pub fn simulate_next(&self) -> Option<Token> {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        let mut shadow = self.shadow.lock().unwrap();
+        shadow.resize(nodes.len(), 0);
+
+        let accept = |p: &Node| !p.is_draining(now) && self.is_available(p, now);
+        let tier = nodes.iter().filter(|p| accept(p)).map(|p| p.priority).min()?;
+
+        let mut tw: i32 = 0;
+        let mut best: Option<usize> = None;
+        for (i, p) in nodes.iter().enumerate() {
+            if p.priority != tier || !accept(p) {
+                continue;
+            }
+
+            let ew = p.ew.min(p.ceiling(now, self.rounding)) as i32;
+            tw = tw.saturating_add(ew);
+            shadow[i] = shadow[i].saturating_add(ew);
+            if best.is_none_or(|x| shadow[i] > shadow[x]) {
+                best = Some(i);
+            }
+        }
+
+        let best = best?;
+        shadow[best] = shadow[best].saturating_sub(tw);
+        self.simulated[best].fetch_add(1, Ordering::Relaxed);
+        Some(nodes[best].token)
+    }
+
+    /// Get simulated selections by token, since the last topology swap,
+    /// see [`simulate_next`](Self::simulate_next).
+    pub fn simulated(&self) -> Vec<u64> {
+        self.simulated[..self.total() as usize]
+            .iter()
+            .map(|x| x.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// An independent deep copy of the balancer, for what-if analysis,
+    /// e.g. of a peer failing.
+    ///
+    /// Peers, their schedule, health and counters are copied, so the fork
+    /// selects exactly as this one would. The clock is shared, while the
+    /// probe hook, selection history, log sampling and fast path are not.
+    pub fn fork(&self) -> Self {
+        fn copy_u32(x: &[AtomicU32]) -> Box<[AtomicU32]> {
+            x.iter().map(|x| AtomicU32::new(x.load(Ordering::Relaxed))).collect()
+        }
+        fn copy_u64(x: &[AtomicU64]) -> Box<[AtomicU64]> {
+            x.iter().map(|x| AtomicU64::new(x.load(Ordering::Relaxed))).collect()
+        }
+        fn copy_bool(x: &[AtomicBool]) -> Box<[AtomicBool]> {
+            x.iter().map(|x| AtomicBool::new(x.load(Ordering::Relaxed))).collect()
+        }
+
+        let _reconfig = self.reconfig.lock().unwrap();
+        let nodes = self.nodes.lock().unwrap();
+        let shadow = self.shadow.lock().unwrap();
+
+        Self {
+            nodes: Mutex::new(nodes.clone()),
+            reconfig: Mutex::new(()),
+            closed: AtomicBool::new(self.closed.load(Ordering::Relaxed)),
+            generation: AtomicU64::new(self.generation()),
+            degraded_since: AtomicU32::new(self.degraded_since.load(Ordering::Relaxed)),
+            last: AtomicU16::new(self.last.load(Ordering::Relaxed)),
+            #[cfg(feature = "lock-time")]
+            lock_time: Mutex::new(self.lock_time.lock().unwrap().clone()),
+            total: AtomicU8::new(self.total()),
+            health: self.health,
+            clock: self.clock.clone(),
+            released: Notify::default(),
+            health_changed: Notify::default(),
+            conns: copy_u32(&self.conns),
+            reserved: copy_u32(&self.reserved),
+            selections: copy_u64(&self.selections),
+            successes: copy_u64(&self.successes),
+            shadow: Mutex::new(shadow.clone()),
+            topk: Mutex::new(self.topk.lock().unwrap().clone()),
+            simulated: copy_u64(&self.simulated),
+            probing: copy_bool(&self.probing),
+            disabled: copy_bool(&self.disabled),
+            trusted: copy_bool(&self.trusted),
+            trust_fraction: self.trust_fraction,
+            trust_turns: AtomicU64::new(self.trust_turns.load(Ordering::Relaxed)),
+            min_share: self.min_share,
+            floor_turns: AtomicU64::new(self.floor_turns.load(Ordering::Relaxed)),
+            floor_next: AtomicUsize::new(self.floor_next.load(Ordering::Relaxed)),
+            probe_hook: None,
+            drain_policy: self.drain_policy,
+            rounding: self.rounding,
+            probation: self.probation,
+            error_tracking: self.error_tracking,
+            probation_at: AtomicU32::new(self.probation_at.load(Ordering::Relaxed)),
+            sampler: Sampler::default(),
+            history: History::default(),
+            audit: AuditLog::default(),
+            #[cfg(feature = "record")]
+            recorder: Recorder::default(),
+            fast: FastPath::default(),
+            canary: self.canary.fork(),
+            shedder: self.shedder.fork(),
+            trickle: self.trickle.fork(),
+            snapshot_interval: self.snapshot_interval,
+            start_offset: self.start_offset,
+            stagger_seed: self.stagger_seed,
+            max_cycle_length: self.max_cycle_length,
+            min_healthy_fraction: self.min_healthy_fraction,
+            duplicates: self.duplicates,
+            next_id: AtomicU64::new(self.next_id.load(Ordering::Relaxed)),
+            labels: RwLock::new(self.labels.read().unwrap().clone()),
+            published: RwLock::new(None),
+        }
+    }
+
+    /// Gini coefficient of selections over peers, from 0 (perfectly even)
+    /// to 1 (all on one peer), regardless of weights.
+    ///
+    /// Counted since the last topology swap, 0 if nothing was selected.
+    pub fn selection_gini(&self) -> f64 {
+        let mut counts: Vec<f64> = self.selections[..self.total() as usize]
+            .iter()
+            .map(|x| x.load(Ordering::Relaxed) as f64)
+            .collect();
+        let n = counts.len() as f64;
+        let sum: f64 = counts.iter().sum();
+        if counts.len() <= 1 || sum == 0.0 {
+            return 0.0;
+        }
+
+        counts.sort_by(f64::total_cmp);
+        let weighted: f64 = counts
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (2.0 * (i + 1) as f64 - n - 1.0) * x)
+            .sum();
+
+        // scaled so that all on one peer is 1
+        weighted / (n * sum) * n / (n - 1.0)
+    }
+
+    /// Send a fixed fraction of selections to a canary peer, regardless of weights.
+    /// The rest is balanced over the other peers.
+    ///
+    /// If the canary is unavailable, its turns go to the other peers.
+    pub fn set_canary(&self, token: Token, fraction: f32) {
+        self.canary.set(token, fraction);
+    }
+
+    /// Stop sending a fixed fraction to the canary peer.
+    pub fn clear_canary(&self) {
+        self.canary.clear();
+    }
+
+    /// Limit concurrent connections of a peer, 0 means unlimited.
+    /// A saturated peer is skipped by [`next`](Balance::next).
+    pub fn set_max_conns(&self, token: Token, max_conns: u32) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.max_conns = max_conns;
+            if max_conns > 0 {
+                self.forget_last(token);
+            }
+        }
+        drop(nodes);
+        self.fast.invalidate();
+    }
+
+    /// Hold `n` connections of a peer's `max_conns` for connections
+    /// about to be opened outside of [`next`](Balance::next),
+    /// e.g. by a batch job, so that selections do not over-commit it.
+    ///
+    /// Held until [`release_reservation`](Self::release_reservation).
+    pub fn reserve(&self, token: Token, n: u32) {
+        if !self.is_known(token) {
+            return;
+        }
+        let _ = self.reserved[token.0 as usize]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(x.saturating_add(n)));
+    }
+
+    /// Give back `n` reserved connections of a peer.
+    pub fn release_reservation(&self, token: Token, n: u32) {
+        let _ = self.reserved[token.0 as usize]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(x.saturating_sub(n)));
+        self.released.notify_all();
+    }
+
+    /// Get reserved connections of a peer.
+    pub fn reserved_conns(&self, token: Token) -> u32 {
+        self.reserved[token.0 as usize].load(Ordering::Relaxed)
+    }
+
+    /// Temporarily scale the weight of a peer, below 1.0 to shed traffic,
+    /// above 1.0 to boost. The configured weight is kept.
+    ///
+    /// Shedding takes effect at once, boosting ramps up like a recovery.
+    /// A weight scaled past 255 is clamped to it, with a warning.
+    pub fn scale_weight(&self, token: Token, factor: f32) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            let factor = if factor.is_nan() { 1.0 } else { factor.max(0.0) };
+            p.target = saturating_scale(p.fitted, factor, self.rounding);
+            warn_clamped(token, p.fitted, factor);
+            p.ew = p.ew.min(p.target);
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Report the load of a peer, from 0 (idle) to 255 (full).
+    ///
+    /// The peer takes a share of traffic cut by its load at once,
+    /// which recovers as the report fades out, unless reported again.
+    pub fn report_load(&self, token: Token, load: u8) {
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.load = load;
+            p.load_at = now;
+            p.ew = p.ew.min(p.ceiling(now, self.rounding));
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Scale the weight of a peer for `duration_secs`, then revert by itself.
+    ///
+    /// Unlike [`scale_weight`](Self::scale_weight), this applies on top
+    /// of a scaled weight, a new boost replaces the previous one.
+    /// Clamped to 255 like a scaled weight.
+    pub fn boost(&self, token: Token, multiplier: f32, duration_secs: u32) {
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        if let Some(p) = nodes.get_mut(token.0 as usize) {
+            p.boost = if multiplier.is_nan() { 1.0 } else { multiplier.max(0.0) };
+            p.boost_until = now.saturating_add(duration_secs);
+            warn_clamped(token, p.target, p.boost);
+            p.ew = p.ew.min(p.ceiling(now, self.rounding));
+        }
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Restore the configured weight of a peer.
+    pub fn reset_scale(&self, token: Token) {
+        self.scale_weight(token, 1.0);
+    }
+
+    /// Select next peer, wait up to `timeout` for a connection
+    /// to be released if all peers are unavailable.
+    pub async fn acquire(&self, state: &(), timeout: Duration) -> Result<Token, Timeout> {
+        let mut deadline = Deadline::after(timeout);
+        std::future::poll_fn(|cx| {
+            // register before trying, so that a release
+            // in between is not missed
+            self.released.register(cx.waker());
+            match self.next(state) {
+                Some(token) => Poll::Ready(Ok(token)),
+                None if deadline.poll_expired(cx) => Poll::Ready(Err(Timeout)),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Select `r` distinct available peers for a quorum,
+    /// spread by the same smooth weighted order as [`next`](Balance::next).
+    ///
+    /// Fails without selecting anything if fewer than `r` are available.
+    pub fn select_quorum(&self, _: &(), r: usize) -> Result<SmallVec<[Token; 8]>, BalanceError> {
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+
+        let available = nodes
+            .iter()
+            .filter(|p| !p.is_draining(now) && self.is_available(p, now))
+            .count();
+        if available < r {
+            return Err(BalanceError::NotEnoughPeers { wanted: r, available });
+        }
+
+        let mut tokens: SmallVec<[Token; 8]> = SmallVec::new();
+        let mut probes: SmallVec<[Token; 8]> = SmallVec::new();
+        while tokens.len() < r {
+            let Some(p) = self.pick(&mut nodes, now, |p| !p.is_draining(now) && !tokens.contains(&p.token)) else {
+                break;
+            };
+            if self.admit(p, now) {
+                probes.push(p.token);
+            }
+            tokens.push(p.token);
+        }
+        drop(nodes);
+
+        if let Some(hook) = &self.probe_hook {
+            probes.iter().for_each(|token| hook.call(*token));
+        }
+
+        Ok(tokens)
+    }
+
+    /// Get the last selections, oldest first.
+    ///
+    /// Always empty unless built [`with_selection_history`](Self::with_selection_history).
+    pub fn recent_selections(&self) -> Vec<SelectionRecord> {
+        self.history.records()
+    }
+
+    /// Get the last recorded calls, oldest first, to [`replay`](crate::record::replay) them.
+    ///
+    /// Always empty unless built [`with_recording`](Self::with_recording).
+    #[cfg(feature = "record")]
+    pub fn recorded(&self) -> Vec<Event> {
+        self.recorder.events()
+    }
+
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| p.weight).collect()
+    }
+
+    /// Suggest weights in proportion to the capacity peers demonstrated:
+    /// reported successes since the last topology swap, worth more the
+    /// lower the reported latency. The largest is 100, a peer without
+    /// successes gets 0. Nothing is applied, see [`set_weights`](Self::set_weights).
+    pub fn recommend_weights(&self) -> Vec<(Token, u8)> {
+        let nodes = self.nodes.lock().unwrap();
+        let known: Vec<f64> = nodes.iter().filter_map(|p| p.latency_ewma).collect();
+        let mean = known.iter().sum::<f64>() / known.len().max(1) as f64;
+
+        let scores: Vec<f64> = nodes
+            .iter()
+            .map(|p| {
+                let successes = self.successes[p.token.0 as usize].load(Ordering::Relaxed) as f64;
+                match p.latency_ewma {
+                    Some(x) if x > 0.0 => successes * mean / x,
+                    _ => successes,
+                }
+            })
+            .collect();
+        let max = scores.iter().cloned().fold(0.0, f64::max);
+
+        nodes
+            .iter()
+            .zip(scores)
+            .map(|(p, score)| {
+                let weight = match max {
+                    0.0 => 0,
+                    _ => (score / max * 100.0).round().max(score.min(1.0)) as u8,
+                };
+                (p.token, weight)
+            })
+            .collect()
+    }
+
+    /// Get effective weights of all peers, what selection runs on.
+    /// Below the configured weight while recovering or scaled down.
+    pub fn effective_weights(&self) -> Vec<u8> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().map(|p| p.ew).collect()
+    }
+
+    /// Get the first `len` selections of a fresh balancer
+    /// with the same weights, the current one is not touched.
+    pub fn selection_sequence(&self, len: usize) -> Vec<Token> {
+        if self.total() == 1 {
+            return vec![Token(0); len];
+        }
+
+        let fitted: Vec<u8> = self.nodes.lock().unwrap().iter().map(|p| p.fitted).collect();
+        let fresh = RoundRobin::new(&fitted);
+        (0..len).filter_map(|_| fresh.next(&())).collect()
+    }
+
+    /// Replace all peers at once.
+    ///
+    /// A new peer takes over the health state, connection setup time
+    /// and active connections of a previous peer with the same key,
+    /// or the same address without a key, the rest start fresh.
+    /// Each previous peer is taken over at most once.
+    ///
+    /// Specs are handled like on construction, i.e. by the same
+    /// [`DuplicatePolicy`], all zero weights falling back to equal
+    /// ones. The canary is cleared, as its token may now be another peer.
+    pub fn swap_topology(&self, specs: &[NodeSpec]) {
+        let specs = &self.duplicates.apply(specs);
+        assert!(specs.len() <= u8::MAX as usize);
+
+        // peers only move under the reconfig lock,
+        // so the previous positions hold until the swap
+        let _reconfig = self.reconfig.lock().unwrap();
+        let ids: Vec<(Option<u64>, Option<SocketAddr>)> =
+            self.nodes.lock().unwrap().iter().map(|p| (p.key, p.addr)).collect();
+        let mut taken = vec![false; ids.len()];
+        let prevs: Vec<Option<usize>> = specs
+            .iter()
+            .map(|spec| {
+                let prev = ids.iter().zip(&taken).position(|((key, addr), taken)| {
+                    !taken
+                        && match spec.key {
+                            Some(x) => *key == Some(x),
+                            None => *addr == Some(spec.addr),
+                        }
+                })?;
+                taken[prev] = true;
+                Some(prev)
+            })
+            .collect();
+        let mut new_nodes: Vec<Node> = specs
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| Node::from_spec(Token(i as u8), spec))
+            .collect();
+        let mut weights: Vec<u8> = new_nodes.iter().map(|p| p.weight).collect();
+        if weights.len() > 1 && weights.iter().all(|w| *w == 0) {
+            log::warn!("[lb]all weights are zero, fallback to equal weights");
+            weights.fill(1);
+        }
+        let fitted = self.fit_cycle(&weights);
+        for (p, (w, f)) in new_nodes.iter_mut().zip(weights.iter().zip(fitted)) {
+            (p.weight, p.fitted, p.target, p.ew) = (*w, f, f, f);
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        let conns: Vec<u32> = (0..MAX_PEERS).map(|i| self.conns[i].load(Ordering::Relaxed)).collect();
+        let reserved: Vec<u32> = (0..MAX_PEERS)
+            .map(|i| self.reserved[i].load(Ordering::Relaxed))
+            .collect();
+        let trusted: Vec<bool> = (0..MAX_PEERS)
+            .map(|i| self.trusted[i].load(Ordering::Relaxed))
+            .collect();
+        let disabled: Vec<bool> = (0..MAX_PEERS)
+            .map(|i| self.disabled[i].load(Ordering::Relaxed))
+            .collect();
+
+        for (i, (node, prev)) in new_nodes.iter_mut().zip(&prevs).enumerate() {
+            let prev_conns = prev.map_or(0, |x| conns[x]);
+            self.trusted[i].store(prev.is_some_and(|x| trusted[x]), Ordering::Relaxed);
+            self.disabled[i].store(prev.is_some_and(|x| disabled[x]), Ordering::Relaxed);
+            if let Some(prev) = prev.map(|x| &nodes[x]) {
+                node.ew = prev.ew.min(node.fitted);
+                node.health = prev.health;
+                node.connect_ewma = prev.connect_ewma;
+                node.latency_ewma = prev.latency_ewma;
+                node.error_ewma = prev.error_ewma;
+                node.probation = prev.probation;
+            }
+
+            self.conns[i].store(prev_conns, Ordering::Relaxed);
+            self.reserved[i].store(prev.map_or(0, |x| reserved[x]), Ordering::Relaxed);
+        }
+
+        for x in self.conns[specs.len()..].iter() {
+            x.store(0, Ordering::Relaxed);
+        }
+        for x in self.reserved[specs.len()..].iter() {
+            x.store(0, Ordering::Relaxed);
+        }
+
+        for x in self
+            .selections
+            .iter()
+            .chain(self.successes.iter())
+            .chain(self.simulated.iter())
+        {
+            x.store(0, Ordering::Relaxed);
+        }
+        self.shadow.lock().unwrap().clear();
+
+        for x in self.probing.iter() {
+            x.store(false, Ordering::Relaxed);
+        }
+
+        *nodes = new_nodes;
+        *self.labels.write().unwrap() = specs.iter().map(|x| x.labels.clone()).collect();
+        self.total.store(specs.len() as u8, Ordering::Relaxed);
+        self.last.store(NO_TOKEN, Ordering::Relaxed);
+        self.fast.invalidate();
+        self.canary.clear();
+        self.generation.fetch_add(1, Ordering::Release);
+        let event = AuditEvent::TopologySwapped {
+            total: specs.len() as u8,
+        };
+        self.audit.push(self.clock.now(), None, event);
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Change the configured weights of all peers at once, in token order.
+    /// Extra weights are ignored, missing ones are left unchanged.
+    ///
+    /// Peers keep their health state and connections, and a runtime
+    /// scaling is reset. All zero, like in [`new`](Balance::new),
+    /// falls back to equal weights.
+    ///
+    /// The weights are fitted to [`with_max_cycle_length`](Self::with_max_cycle_length)
+    /// before taking the peer list, then applied under the same lock as
+    /// selection, which waits for that O(peers) update.
+    pub fn set_weights(&self, weights: &[u8]) {
+        let weights: Vec<Option<u8>> = weights.iter().copied().map(Some).collect();
+        self.reweight(&weights);
+    }
+
+    /// Change the configured weight of a peer, like [`set_weights`](Self::set_weights).
+    ///
+    /// Lowered below its effective weight, e.g. while recovering, the
+    /// effective weight is clamped at once. Raised, it ramps up as usual.
+    pub fn set_weight(&self, token: Token, weight: u8) {
+        if !self.is_known(token) {
+            return;
+        }
+        let mut weights = vec![None; token.0 as usize + 1];
+        weights[token.0 as usize] = Some(weight);
+        self.reweight(&weights);
+    }
+
+    /// Set the given weights, in token order.
+    fn reweight(&self, weights: &[Option<u8>]) {
+        let _reconfig = self.reconfig.lock().unwrap();
+
+        // weights only change under the reconfig lock,
+        // so they are fitted without blocking selection
+        let mut configured: Vec<u8> = self.nodes.lock().unwrap().iter().map(|p| p.weight).collect();
+        configured
+            .iter_mut()
+            .zip(weights)
+            .for_each(|(x, w)| *x = w.unwrap_or(*x));
+        if configured.len() > 1 && configured.iter().all(|w| *w == 0) {
+            log::warn!("[lb]all weights are zero, fallback to equal weights");
+            configured.fill(1);
+        }
+        let fitted = self.fit_cycle(&configured);
+
+        let now = self.clock.now();
+        let mut nodes = self.nodes.lock().unwrap();
+        self.last.store(NO_TOKEN, Ordering::Relaxed);
+        self.fast.invalidate();
+
+        for (i, (p, (w, f))) in nodes.iter_mut().zip(configured.into_iter().zip(fitted)).enumerate() {
+            if weights.get(i).is_some_and(Option::is_some) || w != p.weight || f != p.fitted {
+                if w != p.weight {
+                    let event = AuditEvent::WeightChanged { from: p.weight, to: w };
+                    self.audit.push(now, Some(p.token), event);
+                }
+                p.weight = w;
+                p.fitted = f;
+                p.target = f;
+                // never above the new weight, even boosted
+                p.ew = p.ew.min(p.ceiling(now, self.rounding)).min(f);
+            }
+
+            // restart the cycle with the new weights
+            p.cw = 0;
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+        drop(nodes);
+        self.invalidate_snapshot();
+    }
+
+    /// Get the number of [`is_healthy`](Self::is_healthy) peers.
+    pub fn healthy_count(&self) -> u8 {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        nodes.iter().filter(|p| self.is_in_service(p, now)).count() as u8
+    }
+
+    /// Get the health of the pool as a whole.
+    pub fn pool_status(&self) -> PoolStatus {
+        let total = self.total();
+        let healthy = self.healthy_count();
+        if healthy == total && total != 0 {
+            return PoolStatus::Healthy;
+        }
+
+        if healthy != 0 && healthy as f32 >= total as f32 * self.min_healthy_fraction {
+            PoolStatus::Degraded
+        } else {
+            PoolStatus::Down
+        }
+    }
+
+    /// Get how long the pool has been degraded, i.e. since every
+    /// peer was last in rotation at once, 0 if all of them are now.
+    pub fn degraded_for_secs(&self) -> u32 {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        if nodes.iter().all(|p| self.is_in_service(p, now)) {
+            return 0;
+        }
+        match self.degraded_since.load(Ordering::Relaxed) {
+            NEVER_DEGRADED => 0,
+            since => now.saturating_sub(since),
+        }
+    }
+
+    /// A peer just went out of rotation, start a degraded
+    /// period if all the others are still in.
+    ///
+    /// Ejections end by timing out, unnoticed, so the pool is known
+    /// to have been healthy in between if every peer is in right now.
+    fn mark_degraded(&self, nodes: &[Node], token: Token, now: u32) {
+        if nodes.iter().all(|p| p.token == token || self.is_in_service(p, now)) {
+            self.degraded_since.store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait until at least `min` peers are [`is_healthy`](Self::is_healthy).
+    ///
+    /// Checked again on every health report, and once the earliest
+    /// ejection or unhealthy report times out, even without traffic.
+    pub async fn wait_until_healthy(&self, min: u8) {
+        let mut timer: Option<(u32, Deadline)> = None;
+        std::future::poll_fn(|cx| {
+            self.health_changed.register(cx.waker());
+            if self.healthy_count() >= min {
+                return Poll::Ready(());
+            }
+
+            let now = self.clock.now();
+            let Some(at) = self.next_recovery(now) else {
+                return Poll::Pending;
+            };
+            if timer.as_ref().is_none_or(|(x, _)| *x != at) {
+                let deadline = Deadline::after(Duration::from_secs(at.saturating_sub(now) as u64));
+                timer = Some((at, deadline));
+            }
+            if timer.as_mut().is_some_and(|(_, x)| x.poll_expired(cx)) {
+                // check again, at least a second later than before
+                timer = None;
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// When the next peer out of rotation is back on its own,
+    /// i.e. its ejection or unhealthy report times out.
+    fn next_recovery(&self, now: u32) -> Option<u32> {
+        let nodes = self.nodes.lock().unwrap();
+        nodes
+            .iter()
+            .filter(|p| self.is_enabled(p.token) && !self.is_usable(p, now))
+            .filter_map(|p| {
+                let ejected = self.health_of(p).and_then(|health| {
+                    let at = p.health.ejected?;
+                    p.health
+                        .is_ejected(&health, now)
+                        .then(|| at.saturating_add(p.health.timeout(&health)).saturating_add(1))
+                });
+                let sick = (now < p.sick_until).then_some(p.sick_until);
+                ejected.max(sick)
+            })
+            .min()
+    }
+
+    /// Get the state of all peers.
+    pub fn snapshot(&self) -> BalanceSnapshot {
+        let mut nodes = Vec::new();
+        self.snapshot_into(&mut nodes);
+        BalanceSnapshot {
+            nodes,
+            #[cfg(feature = "lock-time")]
+            select_lock_p99_micros: self.select_lock_micros(0.99),
+        }
+    }
+
+    /// Time a selection held the peer list at quantile `q`,
+    /// from 0.0 to 1.0, None if nothing was selected.
+    #[cfg(feature = "lock-time")]
+    pub fn select_lock_micros(&self, q: f64) -> Option<u64> {
+        self.lock_time.lock().unwrap().quantile(q)
+    }
+
+    /// Same as [`snapshot`](Self::snapshot), but refill `buf`,
+    /// which does not allocate once it is large enough.
+    pub fn snapshot_into(&self, buf: &mut Vec<NodeStat>) {
+        buf.clear();
+        if self.snapshot_interval == 0 {
+            self.collect_into(buf);
+        } else {
+            buf.extend_from_slice(&self.published().nodes);
+        }
+    }
+
+    /// Get the published snapshot, republish it if outdated.
+    pub fn published(&self) -> Arc<BalanceSnapshot> {
+        let now = self.clock.now();
+
+        if let Some((at, snapshot)) = &*self.published.read().unwrap() {
+            if now.saturating_sub(*at) < self.snapshot_interval {
+                return snapshot.clone();
+            }
+        }
+
+        let snapshot = Arc::new(self.collect());
+        *self.published.write().unwrap() = Some((now, snapshot.clone()));
+        snapshot
+    }
+
+    fn invalidate_snapshot(&self) {
+        if self.snapshot_interval != 0 {
+            *self.published.write().unwrap() = None;
+        }
+    }
+
+    fn collect(&self) -> BalanceSnapshot {
+        let mut nodes = Vec::new();
+        self.collect_into(&mut nodes);
+        BalanceSnapshot {
+            nodes,
+            #[cfg(feature = "lock-time")]
+            select_lock_p99_micros: self.select_lock_micros(0.99),
+        }
+    }
+
+    fn collect_into(&self, buf: &mut Vec<NodeStat>) {
+        let now = self.clock.now();
+        let nodes = self.nodes.lock().unwrap();
+        let labels = self.labels.read().unwrap();
+
+        let stats = nodes.iter().map(|p| NodeStat {
+            token: p.token,
+            key: p.key,
+            configured_weight: p.weight,
+            target_weight: p.target,
+            ew: p.ew,
+            fails: p.health.fails,
+            conns: self.active_conns(p.token),
+            state: if self.is_usable(p, now) {
+                NodeState::Healthy
+            } else {
+                NodeState::Ejected
+            },
+            connect_ewma_micros: p.connect_ewma.map(|x| x as u64),
+            labels: labels.get(p.token.0 as usize).cloned().unwrap_or_default(),
+        });
+        buf.extend(stats);
+    }
+
+    /// Compare current state with a prior snapshot.
+    pub fn diff(&self, prev: &BalanceSnapshot) -> Vec<NodeDelta> {
+        self.snapshot().diff(prev)
+    }
+}
+
+/// Scale a weight, clamped to `u8::MAX` rather than wrapped.
+fn saturating_scale(weight: u8, factor: f32, rounding: RoundingPolicy) -> u8 {
+    rounding.apply(weight as f32 * factor).clamp(0.0, u8::MAX as f32) as u8
+}
+
+fn warn_clamped(token: Token, weight: u8, factor: f32) {
+    if weight as f32 * factor > u8::MAX as f32 {
+        log::warn!(
+            "[lb]weight {} of peer {} scaled by {}, clamped to {}",
+            weight,
+            token.0,
+            factor,
+            u8::MAX
+        );
+    }
+}
+
+/// Whether the bit of a token is set, tokens past 63 have none.
+fn is_masked(mask: u64, token: Token) -> bool {
+    token.0 < 64 && mask >> token.0 & 1 == 1
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Number of selections after which every peer got exactly its share.
+fn cycle_of(weights: impl Iterator<Item = u8> + Clone) -> u32 {
+    let sum: u32 = weights.clone().map(|w| w as u32).sum();
+    let gcd = weights.map(|w| w as u32).fold(0, gcd);
+    sum / gcd.max(1)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use average::{Max, Mean, Min};
+    use std::task::{Context, Wake, Waker};
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = std::pin::pin!(fut);
+        loop {
+            if let Poll::Ready(x) = fut.as_mut().poll(&mut cx) {
+                return x;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn rr_same_weight() {
+        let rr = RoundRobin::new(&vec![1; 255]);
+        let mut distro = [0f64; 255];
+
+        for _ in 0..1_000_000 {
+            let token = rr.next(&()).unwrap();
+            distro[token.0 as usize] += 1 as f64;
+        }
+
+        let diffs: Vec<f64> = distro
+            .iter()
+            .map(|x| *x / 1_000_000.0 - 1.0 / 255.0)
+            .map(f64::abs)
+            .inspect(|x| assert!(x < &1e-3))
+            .collect();
+
+        let min_diff: Min = diffs.iter().collect();
+        let max_diff: Max = diffs.iter().collect();
+        let mean_diff: Mean = diffs.iter().collect();
+
+        println!("{:?}", distro);
+        println!("min diff: {}", min_diff.min());
+        println!("max diff: {}", max_diff.max());
+        println!("mean diff: {}", mean_diff.mean());
+    }
+
+    #[test]
+    fn rr_all_weights() {
+        let weights: Vec<u8> = (1..=255).collect();
+        let total_weight: f64 = weights.iter().map(|x| *x as f64).sum();
+        let rr = RoundRobin::new(&weights);
+        let mut distro = [0f64; 255];
+
+        for _ in 0..1_000_000 {
+            let token = rr.next(&()).unwrap();
+            distro[token.0 as usize] += 1 as f64;
+        }
+
+        let diffs: Vec<f64> = distro
+            .iter()
+            .enumerate()
+            .map(|(i, x)| *x / 1_000_000.0 - (i as f64 + 1.0) / total_weight)
+            .map(f64::abs)
+            .inspect(|x| assert!(x < &1e-3))
+            .collect();
+
+        let min_diff: Min = diffs.iter().collect();
+        let max_diff: Max = diffs.iter().collect();
+        let mean_diff: Mean = diffs.iter().collect();
+
+        println!("{:?}", distro);
+        println!("min diff: {}", min_diff.min());
+        println!("max diff: {}", max_diff.max());
+        println!("mean diff: {}", mean_diff.mean());
+    }
+
+    #[test]
+    fn rr_snapshot_diff() {
+        use crate::clock::MockClock;
+        use crate::snapshot::Transition;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1, 2, 3], HealthConfig::default()).with_clock(clock.clone());
+
+        let prev = rr.snapshot();
+        assert_eq!(prev.nodes.len(), 3);
+        assert!(prev.nodes.iter().all(|x| x.state == NodeState::Healthy));
+        assert!(rr.diff(&prev).is_empty());
+
+        rr.on_failure(Token(1));
+
+        let deltas = rr.diff(&prev);
+        assert_eq!(
+            deltas,
+            vec![NodeDelta {
+                token: Token(1),
+                transition: Transition::Ejected
+            }]
+        );
+
+        // ejected peer is skipped
+        for _ in 0..16 {
+            assert_ne!(rr.next(&()), Some(Token(1)));
+        }
+
+        let ejected = rr.snapshot();
+        clock.advance(11);
+        let deltas = rr.diff(&ejected);
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].transition, Transition::Recovered);
+    }
+
+    #[test]
+    fn rr_acquire_wait() {
+        use std::thread;
+        use std::time::Duration;
+
+        let rr = Arc::new(RoundRobin::new(&[1, 1]));
+        rr.set_max_conns(Token(0), 1);
+        rr.set_max_conns(Token(1), 1);
+
+        let mut held = vec![rr.next(&()).unwrap(), rr.next(&()).unwrap()];
+        held.sort_unstable_by_key(|x| x.0);
+        assert_eq!(held, vec![Token(0), Token(1)]);
+        assert_eq!(rr.next(&()), None);
+
+        let waiting = {
+            let rr = rr.clone();
+            thread::spawn(move || block_on(rr.acquire(&(), Duration::from_secs(10))))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        rr.on_disconnect(Token(1));
+        assert_eq!(waiting.join().unwrap(), Ok(Token(1)));
+        assert_eq!(rr.next(&()), None);
+
+        // nothing released in time, with a later deadline pending
+        let later = {
+            let rr = rr.clone();
+            thread::spawn(move || block_on(rr.acquire(&(), Duration::from_secs(10))))
+        };
+        thread::sleep(Duration::from_millis(20));
+        let start = std::time::Instant::now();
+        let waiting: Vec<_> = (0..32)
+            .map(|_| {
+                let rr = rr.clone();
+                thread::spawn(move || block_on(rr.acquire(&(), Duration::from_millis(50))))
+            })
+            .collect();
+        for x in waiting {
+            assert_eq!(x.join().unwrap(), Err(Timeout));
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        rr.on_disconnect(Token(0));
+        assert_eq!(later.join().unwrap(), Ok(Token(0)));
+    }
+
+    #[test]
+    fn rr_drain_all() {
+        use std::thread;
+        use std::time::Duration;
+
+        let rr = Arc::new(RoundRobin::new(&[1, 1, 1]).with_drain_policy(DrainPolicy::LastResort));
+        let held = [rr.next(&()).unwrap(), rr.next(&()).unwrap()];
+
+        rr.drain_all();
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(rr.try_next(&()), Err(BalanceError::Draining));
+
+        let waiting = {
+            let rr = rr.clone();
+            thread::spawn(move || block_on(rr.await_drained(Duration::from_secs(10))))
+        };
+
+        rr.on_disconnect(held[0]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        rr.on_disconnect(held[1]);
+        assert_eq!(waiting.join().unwrap(), Ok(()));
+        assert_eq!(rr.total_active_conns(), 0);
+
+        rr.undrain_all();
+        assert!(rr.next(&()).is_some());
+
+        // nothing to wait for
+        let rr = RoundRobin::new(&[1]);
+        rr.drain_all();
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(block_on(rr.await_drained(Duration::ZERO)), Ok(()));
+
+        // a connection never closed
+        let rr = RoundRobin::new(&[1]);
+        let _held = rr.next(&()).unwrap();
+        rr.drain_all();
+        assert_eq!(block_on(rr.await_drained(Duration::from_millis(50))), Err(Timeout));
+    }
+
+    #[test]
+    fn rr_selection_sequence() {
+        let (a, b, c) = (Token(0), Token(1), Token(2));
+        let rr = RoundRobin::new(&[5, 1, 1]);
+
+        assert_eq!(rr.selection_sequence(7), vec![a, a, b, a, c, a, a]);
+        assert_eq!(rr.selection_sequence(14), [[a, a, b, a, c, a, a]; 2].concat());
+
+        // the live schedule is not touched
+        rr.next(&());
+        assert_eq!(rr.selection_sequence(7), vec![a, a, b, a, c, a, a]);
+
+        let rr = RoundRobin::new(&[3]);
+        assert_eq!(rr.selection_sequence(3), vec![a, a, a]);
+    }
+
+    #[test]
+    fn rr_scale_weight() {
+        fn share(rr: &RoundRobin, token: Token) -> f64 {
+            let hits = (0..32_000).filter(|_| rr.next(&()) == Some(token)).count();
+            hits as f64 / 32_000.0
+        }
+
+        let rr = RoundRobin::new(&[4; 8]);
+        let normal = share(&rr, Token(0));
+        assert!((normal - 1.0 / 8.0).abs() < 1e-3);
+
+        rr.scale_weight(Token(0), 0.5);
+        let scaled = share(&rr, Token(0));
+        assert!((scaled - 2.0 / 30.0).abs() < 1e-3);
+        assert!(scaled / normal > 0.45 && scaled / normal < 0.6);
+
+        rr.reset_scale(Token(0));
+        assert!((share(&rr, Token(0)) - normal).abs() < 1e-3);
+        assert_eq!(rr.weights(), vec![4; 8]);
+    }
+
+    #[test]
+    fn rr_active_conns() {
+        let rr = RoundRobin::new(&[1, 1, 1]);
+        assert_eq!(rr.total_active_conns(), 0);
+
+        let tokens: Vec<Token> = (0..9).map(|_| rr.next(&()).unwrap()).collect();
+        for i in 0..3 {
+            assert_eq!(rr.active_conns(Token(i)), 3);
+        }
+        assert_eq!(rr.total_active_conns(), 9);
+
+        rr.on_disconnect(tokens[0]);
+        rr.on_disconnect(tokens[0]);
+        assert_eq!(rr.active_conns(tokens[0]), 1);
+        assert_eq!(rr.total_active_conns(), 7);
+
+        let snapshot = rr.snapshot();
+        let conns: Vec<u32> = snapshot.nodes.iter().map(|x| x.conns).collect();
+        assert_eq!(conns.iter().sum::<u32>(), 7);
+        assert_eq!(conns[tokens[0].0 as usize], 1);
+    }
+
+    #[test]
+    fn rr_recovery_grace() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            recovery_grace_secs: 5,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1], health).with_clock(clock.clone());
+        let is_ejected = |rr: &RoundRobin| rr.snapshot().nodes[0].state == NodeState::Ejected;
+
+        rr.on_failure(Token(0));
+        assert!(is_ejected(&rr));
+
+        clock.advance(11);
+        assert!(!is_ejected(&rr));
+        rr.on_success(Token(0));
+
+        // within grace window
+        clock.advance(2);
+        rr.on_failure(Token(0));
+        assert!(!is_ejected(&rr));
+        rr.on_failure(Token(0));
+        assert!(is_ejected(&rr));
+
+        clock.advance(11);
+        rr.on_success(Token(0));
+
+        // after grace window
+        clock.advance(5);
+        rr.on_failure(Token(0));
+        assert!(is_ejected(&rr));
+    }
+
+    #[test]
+    fn rr_effective_weights() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[10, 10], HealthConfig::default()).with_clock(clock.clone());
+        assert_eq!(rr.effective_weights(), vec![10, 10]);
+
+        rr.on_failure(Token(0));
+        assert_eq!(rr.effective_weights(), vec![0, 10]);
+
+        // ramps up once back
+        clock.advance(11);
+        rr.on_success(Token(0));
+        for _ in 0..4 {
+            rr.next(&());
+        }
+        let ew = rr.effective_weights()[0];
+        assert!(ew > 0 && ew < 10, "{}", ew);
+        assert_eq!(rr.weights(), vec![10, 10]);
+
+        for _ in 0..20 {
+            rr.next(&());
+        }
+        assert_eq!(rr.effective_weights(), vec![10, 10]);
+    }
+
+    #[test]
+    fn rr_swap_topology() {
+        use crate::clock::MockClock;
+
+        let spec = |i: u8, weight: u8| NodeSpec::new(SocketAddr::from(([10, 0, 0, i], 80)), weight);
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[], HealthConfig::default()).with_clock(clock);
+        rr.swap_topology(&[spec(1, 1), spec(2, 1), spec(3, 1)]);
+        assert_eq!(rr.total(), 3);
+
+        // eject 10.0.0.2, and hold a connection to 10.0.0.3
+        rr.on_failure(Token(1));
+        while rr.next(&()) != Some(Token(2)) {}
+
+        rr.swap_topology(&[spec(4, 2), spec(3, 2), spec(5, 2), spec(2, 2), spec(6, 2)]);
+        assert_eq!(rr.total(), 5);
+        assert_eq!(rr.weights(), vec![2; 5]);
+
+        let snapshot = rr.snapshot();
+        let states: Vec<NodeState> = snapshot.nodes.iter().map(|x| x.state).collect();
+        use NodeState::*;
+        assert_eq!(states, vec![Healthy, Healthy, Healthy, Ejected, Healthy]);
+        assert_eq!(rr.active_conns(Token(1)), 1);
+        assert_eq!(rr.total_active_conns(), 1);
+
+        for _ in 0..64 {
+            assert_ne!(rr.next(&()), Some(Token(3)));
+        }
+    }
+
+    #[test]
+    fn rr_swap_topology_normalized() {
+        let spec = |i: u8, weight: u8| NodeSpec::new(SocketAddr::from(([10, 0, 0, i], 80)), weight);
+
+        // all zero
+        let rr = RoundRobin::new(&[1]);
+        rr.swap_topology(&[spec(1, 0), spec(2, 0)]);
+        assert_eq!(rr.weights(), vec![1, 1]);
+        assert!(rr.next(&()).is_some());
+
+        // duplicates merged as on construction
+        let rr = RoundRobin::from_specs_with(&[spec(1, 1)], None, DuplicatePolicy::Merge);
+        rr.swap_topology(&[spec(1, 1), spec(1, 2), spec(2, 1)]);
+        assert_eq!(rr.weights(), vec![3, 1]);
+
+        // a connection is carried over once
+        let rr = RoundRobin::from_specs(&[spec(1, 1)], None);
+        rr.next(&());
+        rr.swap_topology(&[spec(1, 1), spec(1, 1)]);
+        assert_eq!(rr.connections(), vec![(Token(0), 1), (Token(1), 0)]);
+
+        // the canary token is now another peer
+        let rr = RoundRobin::from_specs(&[spec(1, 1), spec(2, 1)], None);
+        rr.set_canary(Token(1), 0.9);
+        rr.swap_topology(&[spec(3, 1), spec(4, 1)]);
+        let mut distro = [0; 2];
+        for _ in 0..100 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [50, 50]);
+    }
+
+    #[test]
+    fn rr_stable_keys() {
+        let spec = |i: u8, key: u64| NodeSpec {
+            key: Some(key),
+            ..NodeSpec::new(SocketAddr::from(([10, 0, 0, i], 80)), 1)
+        };
+
+        let rr = RoundRobin::with_health(&[], HealthConfig::default());
+        rr.swap_topology(&[spec(1, 100), spec(2, 200), spec(3, 300)]);
+        assert_eq!(rr.token_for_key(200), Some(Token(1)));
+        assert_eq!(rr.key_for_token(Token(2)), Some(300));
+        rr.on_failure(Token(1));
+
+        // reordered, and 200 moved to another address
+        rr.swap_topology(&[spec(3, 300), spec(9, 200), spec(1, 100)]);
+        assert_eq!(rr.token_for_key(200), Some(Token(1)));
+        assert_eq!(rr.token_for_key(300), Some(Token(0)));
+        assert_eq!(rr.key_for_token(Token(2)), Some(100));
+        assert_eq!(rr.snapshot().nodes[1].key, Some(200));
+        assert_eq!(rr.snapshot().nodes[1].state, NodeState::Ejected);
+
+        // a different key at the same address is a new peer
+        rr.swap_topology(&[spec(9, 201)]);
+        assert_eq!(rr.snapshot().nodes[0].state, NodeState::Healthy);
+        assert_eq!(rr.token_for_key(200), None);
+        assert_eq!(rr.key_for_token(Token(1)), None);
+    }
+
+    #[test]
+    fn rr_next_fast() {
+        let rr = RoundRobin::new(&[1, 2, 3]);
+        let reference = RoundRobin::new(&[1, 2, 3]);
+        for _ in 0..100 {
+            assert_eq!(rr.next_fast(&()), reference.next(&()));
+        }
+
+        let rr = RoundRobin::new(&[1; 4]).with_fast_window(Duration::from_micros(10));
+        let mut distro = [0f64; 4];
+        for _ in 0..200_000 {
+            let token = rr.next_fast(&()).unwrap();
+            distro[token.0 as usize] += 1.0;
+        }
+
+        println!("{:?}", distro);
+        for x in distro {
+            assert!((x / 200_000.0 - 0.25).abs() < 0.1);
+        }
+        assert_eq!(rr.total_active_conns(), 200_000);
+    }
+
+    #[test]
+    fn rr_next_fast_stale() {
+        let spec = |i: u8, weight: u8| NodeSpec::new(SocketAddr::from(([10, 0, 0, i], 80)), weight);
+        let window = Duration::from_secs(60);
+
+        // removed by a swap
+        let rr = RoundRobin::new(&[]).with_fast_window(window);
+        rr.swap_topology(&[spec(1, 1), spec(2, 1), spec(3, 1), spec(4, 1), spec(5, 10)]);
+        assert_eq!(rr.next_fast(&()), Some(Token(4)));
+        rr.swap_topology(&[spec(1, 1), spec(2, 1)]);
+        for _ in 0..3 {
+            assert!(rr.next_fast(&()).unwrap().0 < 2);
+        }
+
+        // capped
+        let rr = RoundRobin::new(&[1, 1]).with_fast_window(window);
+        rr.set_max_conns(Token(0), 1);
+        for _ in 0..3 {
+            rr.next_fast(&());
+        }
+        assert_eq!(rr.active_conns(Token(0)), 1);
+        assert_eq!(rr.active_conns(Token(1)), 2);
+
+        // capped after it was cached
+        let rr = RoundRobin::new(&[1, 1]).with_fast_window(window);
+        assert_eq!(rr.next_fast(&()), Some(Token(0)));
+        rr.set_max_conns(Token(0), 1);
+        for _ in 0..3 {
+            assert_eq!(rr.next_fast(&()), Some(Token(1)));
+        }
+
+        // weighted out
+        let rr = RoundRobin::new(&[1, 1]).with_fast_window(window);
+        assert_eq!(rr.next_fast(&()), Some(Token(0)));
+        rr.set_weight(Token(0), 0);
+        for _ in 0..3 {
+            assert_eq!(rr.next_fast(&()), Some(Token(1)));
+        }
+    }
+
+    #[test]
+    fn rr_connections() {
+        let rr = RoundRobin::new(&[2, 1]);
+        assert_eq!(rr.connections(), vec![(Token(0), 0), (Token(1), 0)]);
+
+        for _ in 0..6 {
+            rr.next(&());
+        }
+        assert_eq!(rr.connections(), vec![(Token(0), 4), (Token(1), 2)]);
+
+        for _ in 0..3 {
+            rr.on_disconnect(Token(1));
+        }
+        rr.on_disconnect(Token(0));
+        assert_eq!(rr.connections(), vec![(Token(0), 3), (Token(1), 0)]);
+    }
+
+    #[test]
+    fn rr_single_peer_health() {
+        use crate::clock::MockClock;
+
+        let rr = RoundRobin::new(&[1]);
+        rr.on_failure(Token(0));
+        assert_eq!(rr.next(&()), Some(Token(0)));
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1], HealthConfig::default()).with_clock(clock.clone());
+        assert_eq!(rr.next(&()), Some(Token(0)));
+
+        rr.on_failure(Token(0));
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(rr.snapshot().nodes[0].state, NodeState::Ejected);
+
+        clock.advance(11);
+        assert_eq!(rr.next(&()), Some(Token(0)));
+    }
+
+    #[test]
+    fn rr_set_weights_zero() {
+        let rr = RoundRobin::new(&[1, 2]);
+        rr.set_weights(&[0, 0]);
+        assert_eq!(rr.weights(), vec![1, 1]);
+        let mut distro = [0; 2];
+        for _ in 0..10 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [5, 5]);
+
+        // one at a time
+        rr.set_weight(Token(0), 0);
+        assert_eq!(rr.weights(), vec![0, 1]);
+        assert!((0..10).all(|_| rr.next(&()) == Some(Token(1))));
+        rr.set_weight(Token(1), 0);
+        assert_eq!(rr.weights(), vec![1, 1]);
+    }
+
+    #[test]
+    fn rr_single_peer_accounting() {
+        let rr = RoundRobin::new(&[1]);
+        rr.set_max_conns(Token(0), 1);
+        assert_eq!(rr.next(&()), Some(Token(0)));
+        assert_eq!(rr.active_conns(Token(0)), 1);
+        assert_eq!(rr.next(&()), None);
+        rr.on_disconnect(Token(0));
+
+        rr.drain(Token(0));
+        assert!(rr.is_all_draining());
+        assert_eq!(rr.try_next(&()), Err(BalanceError::Draining));
+        rr.undrain(Token(0));
+
+        rr.report_unhealthy(Token(0), 10);
+        assert_eq!(rr.next(&()), None);
+    }
+
+    #[test]
+    fn rr_zero_weights() {
+        assert_eq!(RoundRobin::try_new(&[0, 0, 0]).err(), Some(BalanceError::ZeroWeights));
+        assert!(RoundRobin::try_new(&[0, 0, 1]).is_ok());
+
+        let rr = RoundRobin::new(&[0, 0, 0]);
+        assert_eq!(rr.weights(), vec![1, 1, 1]);
+
+        let mut distro = [0; 3];
+        for _ in 0..300 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [100; 3]);
+    }
+
+    #[test]
+    fn rr_soft_weights() {
+        let rr = RoundRobin::new(&[4, 4]);
+
+        rr.scale_weight(Token(0), 0.5);
+        let stat = rr.snapshot().nodes[0].clone();
+        assert_eq!(stat.configured_weight, 4);
+        assert_eq!(stat.target_weight, 2);
+        assert_eq!(stat.ew, 2);
+
+        rr.scale_weight(Token(0), 2.0);
+        let stat = rr.snapshot().nodes[0].clone();
+        assert_eq!(stat.configured_weight, 4);
+        assert_eq!(stat.target_weight, 8);
+        assert_eq!(stat.ew, 2);
+
+        // ramps up toward target
+        rr.next(&());
+        rr.next(&());
+        assert_eq!(rr.snapshot().nodes[0].ew, 4);
+        assert_eq!(rr.weights(), vec![4, 4]);
+    }
+
+    #[test]
+    fn rr_addr() {
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:80", "10.0.0.2:80", "[fd00::3]:443"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+        let specs: Vec<NodeSpec> = addrs.iter().map(|addr| NodeSpec::new(*addr, 1)).collect();
+
+        let rr = RoundRobin::from_specs(&specs, None);
+        for addr in addrs.iter() {
+            let token = rr.next(&()).unwrap();
+            assert_eq!(rr.addr(token), Some(*addr));
+        }
+        assert_eq!(rr.addr(Token(3)), None);
+
+        let rr = RoundRobin::new(&[1, 1]);
+        assert_eq!(rr.addr(Token(0)), None);
+    }
+
+    #[test]
+    fn rr_published_snapshot() {
+        use crate::clock::MockClock;
+        use std::thread;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1, 1, 1], HealthConfig::default())
+            .with_clock(clock.clone())
+            .with_snapshot_interval(1);
+
+        let first = rr.published();
+        rr.next(&());
+        assert!(Arc::ptr_eq(&first, &rr.published()));
+        assert_eq!(rr.snapshot().nodes.iter().map(|x| x.conns).sum::<u32>(), 0);
+
+        clock.advance(1);
+        assert_eq!(rr.snapshot().nodes.iter().map(|x| x.conns).sum::<u32>(), 1);
+
+        // republished on health change
+        rr.on_failure(Token(2));
+        assert_eq!(rr.snapshot().nodes[2].state, NodeState::Ejected);
+
+        // and on operator changes
+        let ops: [fn(&RoundRobin); 4] = [
+            |rr| rr.drain(Token(0)),
+            |rr| rr.undrain(Token(0)),
+            |rr| rr.disable(Token(0)),
+            |rr| rr.enable(Token(0)),
+        ];
+        for op in ops {
+            let prev = rr.published();
+            op(&rr);
+            assert!(!Arc::ptr_eq(&prev, &rr.published()));
+        }
+
+        let rr = Arc::new(RoundRobin::new(&[1, 2, 3]).with_snapshot_interval(1));
+        let selectors: Vec<_> = (0..4)
+            .map(|_| {
+                let rr = rr.clone();
+                thread::spawn(move || {
+                    for _ in 0..50_000 {
+                        let token = rr.next(&()).unwrap();
+                        rr.on_disconnect(token);
+                    }
+                })
+            })
+            .collect();
+        let pollers: Vec<_> = (0..2)
+            .map(|_| {
+                let rr = rr.clone();
+                thread::spawn(move || {
+                    for _ in 0..50_000 {
+                        let snapshot = rr.snapshot();
+                        let tokens: Vec<Token> = snapshot.nodes.iter().map(|x| x.token).collect();
+                        assert_eq!(tokens, vec![Token(0), Token(1), Token(2)]);
+                        assert!(snapshot.nodes.iter().map(|x| x.conns).sum::<u32>() <= 4);
+                    }
+                })
+            })
+            .collect();
+
+        selectors.into_iter().chain(pollers).for_each(|x| x.join().unwrap());
+        assert_eq!(rr.total_active_conns(), 0);
+    }
+
+    #[test]
+    fn rr_failure_class() {
+        use crate::clock::MockClock;
+        use crate::failure::FailurePolicy;
+
+        let policy = FailurePolicy::default()
+            .with(FailureClass::ConnectRefused, FailureAction::Eject)
+            .with(FailureClass::Timeout, FailureAction::Count(2));
+        let health = HealthConfig {
+            max_fails: 3,
+            failure_policy: policy,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1; 5], health).with_clock(Arc::new(MockClock::new(100)));
+
+        for _ in 0..2 {
+            rr.on_failure_classified(Token(0), FailureClass::Dns);
+            rr.on_failure_classified(Token(1), FailureClass::ConnectRefused);
+            rr.on_failure_classified(Token(2), FailureClass::Timeout);
+            rr.on_failure_classified(Token(3), FailureClass::TlsHandshake);
+            rr.on_failure(Token(4));
+        }
+
+        let snapshot = rr.snapshot();
+        let fails: Vec<u32> = snapshot.nodes.iter().map(|x| x.fails).collect();
+        let states: Vec<NodeState> = snapshot.nodes.iter().map(|x| x.state).collect();
+        use NodeState::*;
+        assert_eq!(fails, vec![0, 3, 4, 2, 2]);
+        assert_eq!(states, vec![Healthy, Ejected, Ejected, Healthy, Healthy]);
+    }
+
+    #[test]
+    fn rr_wait_until_healthy() {
+        use crate::clock::MockClock;
+        use std::thread;
+        use std::time::Duration;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = Arc::new(RoundRobin::with_health(&[1, 1, 1], HealthConfig::default()).with_clock(clock));
+        for i in 0..3 {
+            rr.on_failure(Token(i));
+        }
+        assert_eq!(rr.healthy_count(), 0);
+
+        let waiting = {
+            let rr = rr.clone();
+            thread::spawn(move || block_on(rr.wait_until_healthy(2)))
+        };
+
+        rr.on_success(Token(0));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        rr.on_success(Token(2));
+        waiting.join().unwrap();
+        assert_eq!(rr.healthy_count(), 2);
+    }
+
+    #[test]
+    fn rr_wait_until_healthy_timeout() {
+        use crate::clock::MockClock;
+        use std::thread;
+        use std::time::Instant;
+
+        let clock = Arc::new(MockClock::new(100));
+        let config = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 1,
+            ..Default::default()
+        };
+        let rr = Arc::new(RoundRobin::with_health(&[1, 1], config).with_clock(clock.clone()));
+        rr.on_failure(Token(0));
+        rr.report_unhealthy(Token(1), 2);
+        assert_eq!(rr.healthy_count(), 0);
+
+        let start = Instant::now();
+        let waiting = {
+            let rr = rr.clone();
+            thread::spawn(move || block_on(rr.wait_until_healthy(2)))
+        };
+
+        // no report, the ejection and the unhealthy report merely time out
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        clock.advance(2);
+        waiting.join().unwrap();
+        assert_eq!(rr.healthy_count(), 2);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn rr_canary() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1, 1, 1, 10], HealthConfig::default()).with_clock(clock);
+        rr.set_canary(Token(3), 0.1);
+
+        let mut distro = [0; 4];
+        for _ in 0..10_000 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [3000, 3000, 3000, 1000]);
+
+        rr.on_failure(Token(3));
+        let mut distro = [0; 4];
+        for _ in 0..9_000 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [3000, 3000, 3000, 0]);
+
+        rr.clear_canary();
+        rr.on_success(Token(3));
+        let mut distro = [0; 4];
+        for _ in 0..13_000 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert!(distro[3] > 9_000);
+    }
+
+    #[test]
+    fn rr_extreme_weights() {
+        // 255 * 255 overflows an i16
+        let weights = [u8::MAX; 255];
+        let rr = RoundRobin::new(&weights);
+        let mut distro = [0usize; 255];
+        for _ in 0..255 * 255 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert!(distro.iter().all(|x| *x == 255));
+
+        // skewed
+        let mut weights = [1u8; 255];
+        weights[0] = u8::MAX;
+        let rr = RoundRobin::new(&weights);
+        for _ in 0..100_000 {
+            rr.next(&()).unwrap();
+        }
+    }
+
+    #[test]
+    fn rr_select_quorum() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let config = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 2, 3, 4], config).with_clock(clock);
+
+        for _ in 0..100 {
+            let tokens = rr.select_quorum(&(), 3).unwrap();
+            assert_eq!(tokens.len(), 3);
+            assert!(tokens.iter().all(|t| tokens.iter().filter(|x| *x == t).count() == 1));
+        }
+        assert_eq!(rr.select_quorum(&(), 4).unwrap().len(), 4);
+
+        rr.on_failure(Token(1));
+        rr.on_failure(Token(2));
+        let tokens = rr.select_quorum(&(), 2).unwrap();
+        assert!(tokens.contains(&Token(0)) && tokens.contains(&Token(3)));
+        assert_eq!(
+            rr.select_quorum(&(), 3),
+            Err(BalanceError::NotEnoughPeers {
+                wanted: 3,
+                available: 2
+            })
+        );
+    }
+
+    #[test]
+    fn rr_is_healthy() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let config = HealthConfig {
+            max_fails: 2,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 2], config).with_clock(clock.clone());
+        assert!(rr.is_healthy(Token(0)));
+        assert!(rr.is_healthy(Token(1)));
+        assert!(!rr.is_healthy(Token(2)));
+
+        // ejected
+        rr.on_failure(Token(1));
+        assert!(rr.is_healthy(Token(1)));
+        rr.on_failure(Token(1));
+        assert!(!rr.is_healthy(Token(1)));
+
+        // half-open
+        clock.advance(11);
+        assert!(rr.is_healthy(Token(1)));
+
+        // saturated is still healthy
+        rr.set_max_conns(Token(0), 1);
+        rr.next(&());
+        rr.next(&());
+        assert!(rr.is_healthy(Token(0)));
+
+        // draining is still healthy, disabled is not
+        let rr = RoundRobin::with_health(&[1, 2], config).with_clock(clock.clone());
+        rr.drain(Token(0));
+        assert!(rr.is_healthy(Token(0)));
+        rr.disable(Token(0));
+        assert!(!rr.is_healthy(Token(0)));
+        assert_eq!(rr.healthy_count(), 1);
+        assert_eq!(rr.pool_status(), PoolStatus::Degraded);
+        clock.advance(3);
+        assert_eq!(rr.degraded_for_secs(), 3);
+        rr.enable(Token(0));
+        assert!(rr.is_healthy(Token(0)));
+        assert_eq!(rr.pool_status(), PoolStatus::Healthy);
+
+        // without health check
+        let rr = RoundRobin::new(&[1, 2]);
+        rr.on_failure(Token(0));
+        assert!(rr.is_healthy(Token(0)));
+    }
+
+    #[test]
+    fn rr_report_load() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::new(&[10, 10]).with_clock(clock.clone());
+        let share = |rr: &RoundRobin| {
+            let mut n = 0;
+            for _ in 0..1000 {
+                if rr.next(&()).unwrap() == Token(0) {
+                    n += 1;
+                }
+            }
+            n
+        };
+        assert_eq!(share(&rr), 500);
+
+        rr.report_load(Token(0), 230);
+        assert!(share(&rr) < 100);
+
+        // half faded out
+        clock.advance(LOAD_DECAY_SECS / 2);
+        let half = share(&rr);
+        assert!(half > 100 && half < 500);
+
+        // reports ceased
+        clock.advance(LOAD_DECAY_SECS);
+        share(&rr);
+        assert_eq!(share(&rr), 500);
+    }
+
+    #[test]
+    fn rr_recent_selections() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::with_health(&[1, 1], HealthConfig::default())
+            .with_clock(clock.clone())
+            .with_selection_history(4);
+        assert!(rr.recent_selections().is_empty());
+
+        for _ in 0..6 {
+            clock.advance(1);
+            rr.next(&());
+        }
+        rr.on_failure(Token(0));
+        clock.advance(1);
+        rr.next(&());
+
+        let records = rr.recent_selections();
+        let at: Vec<u32> = records.iter().map(|x| x.at).collect();
+        assert_eq!(at, vec![4, 5, 6, 7]);
+        assert_eq!(records[2].healthy, 2);
+        assert_eq!(
+            records[3],
+            SelectionRecord {
+                at: 7,
+                token: Some(Token(1)),
+                healthy: 1
+            }
+        );
+
+        let rr = RoundRobin::new(&[1, 1]);
+        rr.next(&());
+        assert!(rr.recent_selections().is_empty());
+    }
+
+    #[test]
+    fn rr_selection_gini() {
+        let rr = RoundRobin::new(&[1, 1, 1, 1]);
+        assert_eq!(rr.selection_gini(), 0.0);
+        for _ in 0..1000 {
+            rr.next(&());
+        }
+        assert!(rr.selection_gini() < 1e-3);
+
+        let rr = RoundRobin::new(&[255, 1, 1, 1]);
+        for _ in 0..1000 {
+            rr.next(&());
+        }
+        assert!(rr.selection_gini() > 0.9);
+
+        let rr = RoundRobin::new(&[1, 0, 0, 0]);
+        for _ in 0..1000 {
+            rr.next(&());
+        }
+        assert!((rr.selection_gini() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rr_is_ramping() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::with_health(&[4, 4], HealthConfig::default()).with_clock(clock.clone());
+        assert!(!rr.is_ramping(Token(0)));
+
+        rr.on_failure(Token(0));
+        clock.advance(11);
+        rr.on_success(Token(0));
+        assert!(rr.is_ramping(Token(0)));
+        assert!(!rr.is_ramping(Token(1)));
+
+        let mut picks = 0;
+        while rr.is_ramping(Token(0)) {
+            rr.next(&());
+            picks += 1;
+            assert!(picks <= 4);
+        }
+        assert_eq!(rr.snapshot().nodes[0].ew, 4);
+
+        // shed is not ramping
+        rr.scale_weight(Token(1), 0.5);
+        assert!(!rr.is_ramping(Token(1)));
+    }
+
+    #[test]
+    fn rr_unknown_token() {
+        let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let specs: Vec<NodeSpec> = (0..3).map(|i| NodeSpec::new(addr(i), 1)).collect();
+
+        let rr = RoundRobin::with_health(&[1, 1, 1], HealthConfig::default());
+        rr.swap_topology(&specs[..2]);
+        assert!(rr.report_success(Token(1)));
+        assert!(rr.report_failure(Token(1)));
+        assert!(!rr.report_success(Token(2)));
+        assert!(!rr.report_failure(Token(2)));
+
+        let rr = RoundRobin::new(&[1, 1, 1]);
+        rr.swap_topology(&specs[..2]);
+        assert!(rr.report_failure(Token(1)));
+        assert!(!rr.report_failure(Token(2)));
+    }
+
+    #[test]
+    fn rr_try_report() {
+        let rr = RoundRobin::with_health(&[1, 1], HealthConfig::default());
+        assert_eq!(rr.try_on_success(Token(1)), Ok(()));
+        assert_eq!(rr.try_on_failure(Token(1)), Ok(()));
+        assert_eq!(rr.try_on_success(Token(2)), Err(BalanceError::UnknownToken(Token(2))));
+        assert_eq!(rr.try_on_failure(Token(9)), Err(BalanceError::UnknownToken(Token(9))));
+        assert_eq!(
+            rr.try_on_failure(Token(9)).unwrap_err().to_string(),
+            "unknown peer token: 9"
+        );
+
+        // also without health check
+        let rr = RoundRobin::new(&[1]);
+        assert_eq!(rr.try_on_failure(Token(0)), Ok(()));
+        assert!(rr.try_on_failure(Token(1)).is_err());
+    }
+
+    #[test]
+    fn rr_boost() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::new(&[10, 10]).with_clock(clock.clone());
+        let share = |rr: &RoundRobin| {
+            let mut n = 0;
+            for _ in 0..1000 {
+                if rr.next(&()).unwrap() == Token(0) {
+                    n += 1;
+                }
+            }
+            n
+        };
+
+        rr.boost(Token(0), 2.0, 30);
+        assert!(share(&rr) > 600);
+        assert_eq!(rr.snapshot().nodes[0].ew, 20);
+
+        clock.advance(29);
+        assert!(share(&rr) > 600);
+
+        // expired
+        clock.advance(1);
+        let n = share(&rr);
+        assert!((490..=510).contains(&n));
+        assert_eq!(rr.snapshot().nodes[0].ew, 10);
+
+        // shed for a while
+        rr.boost(Token(0), 0.5, 10);
+        assert_eq!(rr.snapshot().nodes[0].ew, 5);
+        clock.advance(10);
+        share(&rr);
+        assert_eq!(rr.snapshot().nodes[0].ew, 10);
+    }
+
+    #[test]
+    fn rr_from_specs() {
+        let addr = |i| SocketAddr::from(([10, 0, 0, i], 80));
+        let specs = vec![
+            NodeSpec {
+                tags: vec!["primary".to_string()],
+                max_conns: 2,
+                ..NodeSpec::new(addr(1), 1)
+            },
+            NodeSpec {
+                tags: vec!["primary".to_string(), "ssd".to_string()],
+                ..NodeSpec::new(addr(2), 3)
+            },
+            NodeSpec {
+                tags: vec!["backup".to_string()],
+                priority: 1,
+                ..NodeSpec::new(addr(3), 5)
+            },
+        ];
+        let rr = RoundRobin::from_specs(&specs, Some(HealthConfig::default()));
+
+        assert_eq!(rr.weights(), vec![1, 3, 5]);
+        assert_eq!(rr.addr(Token(2)), Some(addr(3)));
+        assert_eq!(rr.tags(Token(1)), vec!["primary", "ssd"]);
+        assert_eq!(rr.tags(Token(2)), vec!["backup"]);
+        assert_eq!(rr.priority(Token(2)), Some(1));
+        assert_eq!(rr.priority(Token(3)), None);
+
+        // the backup tier is idle while the first tier is up
+        let mut distro = [0; 3];
+        for _ in 0..400 {
+            let token = rr.next(&()).unwrap();
+            distro[token.0 as usize] += 1;
+            rr.on_disconnect(token);
+        }
+        assert_eq!(distro, [100, 300, 0]);
+
+        // saturated
+        while rr.active_conns(Token(0)) < 2 {
+            rr.next(&());
+        }
+        for _ in 0..10 {
+            assert_eq!(rr.next(&()), Some(Token(1)));
+        }
+
+        // fail over to the backup tier
+        rr.on_failure(Token(1));
+        assert_eq!(rr.next(&()), Some(Token(2)));
+    }
+
+    #[test]
+    fn rr_empty() {
+        let rr = RoundRobin::new(&[]);
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(rr.next_fast(&()), None);
+        assert!(rr.selection_sequence(4).is_empty());
+
+        let rr = RoundRobin::with_health(&[], HealthConfig::default());
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(
+            rr.select_quorum(&(), 1).map(|x| x.len()),
+            Err(BalanceError::NotEnoughPeers {
+                wanted: 1,
+                available: 0
+            })
+        );
+
+        // populated later
+        rr.swap_topology(&[NodeSpec::new(SocketAddr::from(([127, 0, 0, 1], 80)), 1)]);
+        assert_eq!(rr.next(&()), Some(Token(0)));
+    }
+
+    #[test]
+    fn rr_ejection_reason() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let config = HealthConfig {
+            max_fails: 3,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1, 1], config).with_clock(clock.clone());
+
+        for _ in 0..2 {
+            rr.on_failure_classified(Token(0), FailureClass::Timeout);
+            rr.on_failure(Token(1));
+        }
+        assert_eq!(rr.ejection_reason(Token(0)), None);
+
+        rr.on_failure_classified(Token(0), FailureClass::Timeout);
+        rr.on_failure(Token(1));
+        assert_eq!(
+            rr.ejection_reason(Token(0)),
+            Some(EjectionReason::Failures(Some(FailureClass::Timeout)))
+        );
+        assert_eq!(rr.ejection_reason(Token(1)), Some(EjectionReason::Failures(None)));
+        assert_eq!(rr.ejection_reason(Token(2)), None);
+
+        // half-open, then succeeds
+        clock.advance(11);
+        assert!(rr.ejection_reason(Token(0)).is_some());
+        rr.on_success(Token(0));
+        assert_eq!(rr.ejection_reason(Token(0)), None);
+    }
+
+    #[test]
+    fn rr_single_probe() {
+        use crate::clock::MockClock;
+        use std::sync::Barrier;
+        use std::thread;
+
+        let clock = Arc::new(MockClock::new(0));
+        let dispatched = Arc::new(AtomicU32::new(0));
+        let rr = RoundRobin::with_health(&[1, 1, 1, 1], HealthConfig::default())
+            .with_clock(clock.clone())
+            .with_probe_hook({
+                let dispatched = dispatched.clone();
+                move |token| {
+                    assert_eq!(token, Token(0));
+                    dispatched.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        rr.on_failure(Token(0));
+        clock.advance(11);
+
+        let probes = AtomicU32::new(0);
+        let barrier = Barrier::new(8);
+        let run = || {
+            thread::scope(|s| {
+                for _ in 0..8 {
+                    s.spawn(|| {
+                        barrier.wait();
+                        for _ in 0..100 {
+                            let token = rr.next(&()).unwrap();
+                            if token == Token(0) {
+                                probes.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                    });
+                }
+            })
+        };
+
+        run();
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+        assert_eq!(dispatched.load(Ordering::SeqCst), 1);
+
+        // still in flight after another timeout
+        clock.advance(11);
+        run();
+        assert_eq!(probes.load(Ordering::SeqCst), 1);
+
+        // resolved
+        rr.on_success(Token(0));
+        run();
+        assert!(probes.load(Ordering::SeqCst) > 100);
+        assert_eq!(dispatched.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn rr_all_draining() {
+        let rr = RoundRobin::new(&[1, 2, 3]);
+        rr.drain(Token(1));
+        for _ in 0..10 {
+            assert_ne!(rr.next(&()), Some(Token(1)));
+        }
+        assert!(!rr.is_all_draining());
+
+        rr.drain(Token(0));
+        rr.drain(Token(2));
+        assert!(rr.is_all_draining());
+        assert_eq!(rr.next(&()), None);
+        assert_eq!(rr.try_next(&()), Err(BalanceError::Draining));
+
+        rr.undrain(Token(2));
+        assert_eq!(rr.try_next(&()), Ok(Token(2)));
+
+        // last resort
+        let rr = RoundRobin::new(&[1, 2, 3]).with_drain_policy(DrainPolicy::LastResort);
+        rr.drain(Token(0));
+        for _ in 0..10 {
+            assert_ne!(rr.next(&()), Some(Token(0)));
+        }
+        rr.drain(Token(1));
+        rr.drain(Token(2));
+        let mut distro = [0; 3];
+        for _ in 0..60 {
+            distro[rr.try_next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [10, 20, 30]);
+    }
+
+    #[test]
+    fn rr_token_index() {
+        use crate::clock::MockClock;
+
+        let addr = |i| SocketAddr::from(([10, 0, 0, i], 80));
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::with_health(&[], HealthConfig::default()).with_clock(clock);
+        rr.swap_topology(&[1, 2, 3, 4].map(|i| NodeSpec::new(addr(i), 1)));
+
+        // 10.0.0.2 is removed, the rest are remapped
+        rr.swap_topology(&[1, 3, 4].map(|i| NodeSpec::new(addr(i), 1)));
+        assert_eq!(rr.addr(Token(1)), Some(addr(3)));
+
+        rr.on_failure(Token(1));
+        assert_eq!(rr.ejection_reason(Token(1)), Some(EjectionReason::Failures(None)));
+        assert!(rr.is_healthy(Token(0)));
+        assert!(rr.is_healthy(Token(2)));
+        for _ in 0..10 {
+            assert_ne!(rr.next(&()), Some(Token(1)));
+        }
+
+        // removed
+        assert!(!rr.report_failure(Token(3)));
+        assert!(!rr.report_success(Token(3)));
+        assert_eq!(rr.addr(Token(3)), None);
+    }
+
+    #[test]
+    fn rr_report_unhealthy() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(0));
+        let rr = RoundRobin::new(&[1, 1, 1]).with_clock(clock.clone());
+        rr.report_unhealthy(Token(1), 5);
+        assert!(!rr.is_healthy(Token(1)));
+        assert_eq!(rr.ejection_reason(Token(1)), Some(EjectionReason::Reported));
+        assert_eq!(rr.snapshot().nodes[1].state, NodeState::Ejected);
+        assert_eq!(rr.snapshot().nodes[1].fails, 0);
+
+        for _ in 0..4 {
+            for _ in 0..10 {
+                assert_ne!(rr.next(&()), Some(Token(1)));
+            }
+            clock.advance(1);
+        }
+
+        // lapsed
+        clock.advance(1);
+        assert!(rr.is_healthy(Token(1)));
+        assert_eq!(rr.ejection_reason(Token(1)), None);
+        assert!((0..3).any(|_| rr.next(&()) == Some(Token(1))));
+    }
+
+    #[test]
+    fn rr_zero_max_fails_rejected() {
+        let config = HealthConfig {
+            max_fails: 0,
+            ..Default::default()
+        };
+        let err = RoundRobin::try_with_health(&[1, 1], config).unwrap_err();
+        assert!(matches!(err, BalanceError::InvalidConfig(_)));
+
+        let rr = RoundRobin::try_with_health(&[1, 1], HealthConfig::default()).unwrap();
+        assert!(rr.next(&()).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "max_fails")]
+    fn rr_zero_max_fails_panics() {
+        let config = HealthConfig {
+            max_fails: 0,
+            ..Default::default()
+        };
+        RoundRobin::with_health(&[1, 1], config);
+    }
+
+    #[test]
+    fn rr_pool_status() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1; 4], HealthConfig::default()).with_clock(clock.clone());
+        assert_eq!(rr.pool_status(), PoolStatus::Healthy);
+
+        rr.on_failure(Token(0));
+        assert_eq!(rr.pool_status(), PoolStatus::Degraded);
+        rr.on_failure(Token(1));
+        assert_eq!(rr.pool_status(), PoolStatus::Degraded);
+        rr.on_failure(Token(2));
+        assert_eq!(rr.pool_status(), PoolStatus::Down);
+        rr.on_failure(Token(3));
+        assert_eq!(rr.pool_status(), PoolStatus::Down);
+
+        // back once the ejections time out
+        clock.advance(11);
+        assert_eq!(rr.pool_status(), PoolStatus::Healthy);
+
+        let rr = RoundRobin::with_health(&[1; 4], HealthConfig::default())
+            .with_clock(clock.clone())
+            .with_min_healthy_fraction(0.25);
+        rr.on_failure(Token(0));
+        rr.on_failure(Token(1));
+        rr.on_failure(Token(2));
+        assert_eq!(rr.pool_status(), PoolStatus::Degraded);
+
+        assert_eq!(RoundRobin::new(&[]).pool_status(), PoolStatus::Down);
+        assert_eq!(RoundRobin::new(&[1, 1]).pool_status(), PoolStatus::Healthy);
+
+        // reported, even without a health check
+        let rr = RoundRobin::new(&[1, 1]).with_clock(clock.clone());
+        rr.report_unhealthy(Token(0), 10);
+        clock.advance(3);
+        assert!(!rr.is_healthy(Token(0)));
+        assert_eq!(rr.healthy_count(), 1);
+        assert_eq!(rr.pool_status(), PoolStatus::Degraded);
+        assert_eq!(rr.degraded_for_secs(), 3);
+    }
+
+    #[test]
+    fn rr_next_traced() {
+        let rr = RoundRobin::new(&[1, 2, 3]);
+        let mut last = 0;
+        for _ in 0..100 {
+            let (token, id) = rr.next_traced(&()).unwrap();
+            assert!(token.0 < 3);
+            assert!(id > last);
+            last = id;
+        }
+
+        let rr = Arc::new(RoundRobin::new(&[1, 2, 3]));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let rr = rr.clone();
+                std::thread::spawn(move || (0..1000).map(|_| rr.next_traced(&()).unwrap().1).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut ids: Vec<u64> = handles.into_iter().flat_map(|x| x.join().unwrap()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 4000);
+
+        // no id without a selection
+        let rr = RoundRobin::new(&[]);
+        assert_eq!(rr.next_traced(&()), None);
+    }
+
+    #[test]
+    fn rr_pool_shedding() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::new(&[1; 4])
+            .with_clock(clock.clone())
+            .with_shedding(ShedConfig {
+                pool_shed_error_rate: 0.5,
+                pool_shed_fraction: 0.3,
+            });
+
+        for _ in 0..100 {
+            let token = rr.next(&()).unwrap();
+            rr.on_success(token);
+        }
+        assert!(!rr.is_shedding());
+
+        // every peer fails
+        for _ in 0..400 {
+            if let Some(token) = rr.next(&()) {
+                rr.on_failure(token);
+            }
+        }
+        assert!(rr.is_shedding());
+        let shed = (0..1000).filter(|_| rr.next(&()).is_none()).count();
+        assert_eq!(shed, 300);
+
+        // errors subside
+        clock.advance(10);
+        for _ in 0..400 {
+            if let Some(token) = rr.next(&()) {
+                rr.on_success(token);
+            }
+        }
+        assert!(!rr.is_shedding());
+        assert!((0..1000).all(|_| rr.next(&()).is_some()));
+    }
+
+    #[test]
+    fn rr_stagger() {
+        let weights = [5, 3, 2, 1, 1];
+        assert_eq!(RoundRobin::new(&weights).cycle_length(), 12);
+        assert_eq!(RoundRobin::new(&[2, 4, 6]).cycle_length(), 6);
+        assert_eq!(RoundRobin::new(&[]).cycle_length(), 0);
+
+        let mut firsts = Vec::new();
+        for seed in 0..32 {
+            let rr = RoundRobin::new(&weights).with_stagger(seed);
+            let len = rr.cycle_length();
+
+            // the very first cycle is already exact
+            let mut distro = [0; 5];
+            let mut first = None;
+            for _ in 0..len {
+                let token = rr.next(&()).unwrap();
+                first.get_or_insert(token);
+                distro[token.0 as usize] += 1;
+            }
+            assert_eq!(distro, weights);
+            firsts.push(first.unwrap());
+        }
+
+        // not all starting at the same peer
+        assert!(firsts.iter().any(|x| *x != firsts[0]));
+    }
+
+    #[test]
+    fn rr_start_offset() {
+        let weights = [5, 3, 2, 1, 1];
+        let seq = |rr: &RoundRobin| -> Vec<Token> { (0..120).map(|_| rr.next(&()).unwrap()).collect() };
+        let a = seq(&RoundRobin::new(&weights));
+        let b = seq(&RoundRobin::new(&weights).with_start_offset(1));
+        assert_eq!(
+            RoundRobin::new(&weights).with_start_offset(17).options().start_offset,
+            17
+        );
+
+        // early picks differ
+        assert_ne!(a[0], b[0]);
+        let same = a[..12].iter().zip(&b[..12]).filter(|(x, y)| x == y).count();
+        assert!(same <= 6, "{} of 12", same);
+
+        // each is still exact
+        for x in [a, b] {
+            let mut distro = [0; 5];
+            for token in x {
+                distro[token.0 as usize] += 1;
+            }
+            assert_eq!(distro, weights.map(|w| w * 10));
+        }
+
+        // wraps around the cycle
+        let a = seq(&RoundRobin::new(&weights).with_start_offset(2));
+        let b = seq(&RoundRobin::new(&weights).with_start_offset(14));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn rr_scale_clamped() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::new(&[200, 200]).with_clock(clock);
+        rr.scale_weight(Token(0), 10.0);
+        assert_eq!(rr.snapshot().nodes[0].target_weight, u8::MAX);
+
+        // saturated, not wrapped around
+        let mut distro = [0; 2];
+        for _ in 0..4550 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert!(distro[0] > distro[1], "{:?}", distro);
+
+        rr.boost(Token(0), f32::MAX, 10);
+        rr.boost(Token(1), 1000.0, 10);
+        let mut distro = [0u32; 2];
+        for _ in 0..5100 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert!(distro[0].abs_diff(distro[1]) < 100, "{:?}", distro);
+    }
+
+    #[test]
+    fn rr_trickle() {
+        use crate::clock::MockClock;
+
+        let run = |rr: &RoundRobin| {
+            let mut distro = [0; 4];
+            for _ in 0..1000 {
+                let token = rr.next(&()).unwrap();
+                distro[token.0 as usize] += 1;
+                rr.on_disconnect(token);
+            }
+            distro
+        };
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1; 4], HealthConfig::default())
+            .with_clock(clock.clone())
+            .with_trickle(2, 0.1);
+        for i in 0..3 {
+            rr.on_failure(Token(i));
+            clock.advance(1);
+        }
+
+        // the first ejected is the closest to recover
+        assert_eq!(run(&rr), [100, 0, 0, 900]);
+
+        // one at a time
+        let probe = (0..10).map(|_| rr.next(&()).unwrap()).find(|x| *x == Token(0));
+        assert_eq!(probe, Some(Token(0)));
+        assert!((0..20).all(|_| rr.next(&()) != Some(Token(0))));
+
+        // recovered, enough left
+        rr.on_success(Token(0));
+        let distro = run(&rr);
+        assert_eq!(distro[1] + distro[2], 0);
+
+        // off by default
+        let rr = RoundRobin::with_health(&[1; 4], HealthConfig::default()).with_clock(clock.clone());
+        for i in 0..3 {
+            rr.on_failure(Token(i));
+        }
+        assert_eq!(run(&rr), [0, 0, 0, 1000]);
+    }
+
+    #[test]
+    fn rr_simulate_next() {
+        let rr = RoundRobin::new(&[5, 3, 2]);
+        rr.next(&());
+        let cw = |rr: &RoundRobin| -> Vec<i32> { rr.nodes.lock().unwrap().iter().map(|p| p.cw).collect() };
+        let before = cw(&rr);
+
+        let mut distro = [0; 3];
+        for _ in 0..1000 {
+            distro[rr.simulate_next().unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [500, 300, 200]);
+        assert_eq!(rr.simulated(), vec![500, 300, 200]);
+
+        // live schedule untouched
+        assert_eq!(cw(&rr), before);
+        assert_eq!(rr.total_active_conns(), 1);
+        let twin = RoundRobin::new(&[5, 3, 2]);
+        twin.next(&());
+        assert!((0..20).all(|_| rr.next(&()) == twin.next(&())));
+
+        // follows draining
+        rr.drain(Token(0));
+        assert!((0..10).all(|_| rr.simulate_next() != Some(Token(0))));
+    }
+
+    #[test]
+    fn rr_stagger_phase() {
+        let weights = [5, 3, 2, 1, 1];
+        let seq = |rr: RoundRobin| -> Vec<Token> { (0..24).map(|_| rr.next(&()).unwrap()).collect() };
+        let a = seq(RoundRobin::new(&weights).with_stagger(1));
+        let b = seq(RoundRobin::new(&weights).with_stagger(2));
+        assert_ne!(a, b);
+
+        // the same schedule, shifted
+        let shift = (1..12).find(|k| a[*k..*k + 12] == b[..12]);
+        assert!(shift.is_some());
+
+        let rr = RoundRobin::new(&weights).with_options(BalanceOptions {
+            stagger_seed: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(rr.options().stagger_seed, Some(2));
+        assert_eq!(seq(rr), b);
+    }
+
+    #[test]
+    fn rr_probe_before_trust() {
+        let run = |rr: &RoundRobin| {
+            let mut distro = [0; 4];
+            for _ in 0..1000 {
+                let token = rr.next(&()).unwrap();
+                distro[token.0 as usize] += 1;
+                rr.on_disconnect(token);
+            }
+            distro
+        };
+
+        let rr = RoundRobin::new(&[1; 4]).with_probe_before_trust(0.1);
+        assert!(!rr.is_trusted(Token(0)));
+
+        // nothing trusted yet
+        assert_eq!(run(&rr), [250; 4]);
+
+        for i in 0..3 {
+            rr.on_success(Token(i));
+        }
+        let distro = run(&rr);
+        assert_eq!(distro[3], 100);
+
+        rr.on_success(Token(3));
+        assert!(rr.is_trusted(Token(3)));
+        assert_eq!(run(&rr), [250; 4]);
+
+        // trusted by default
+        assert!(RoundRobin::new(&[1]).is_trusted(Token(0)));
+    }
+
+    #[test]
+    fn rr_next_explained() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[1, 1], HealthConfig::default()).with_clock(clock.clone());
+        let recoveries = |rr: &RoundRobin| {
+            (0..20)
+                .map(|_| rr.next_explained(&()).unwrap())
+                .filter(|x| x.recovery)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(recoveries(&rr).is_empty());
+        rr.on_failure(Token(0));
+        assert!(recoveries(&rr).is_empty());
+
+        // once per timeout
+        clock.advance(11);
+        let admitted = recoveries(&rr);
+        assert_eq!(
+            admitted,
+            vec![Selection {
+                token: Token(0),
+                recovery: true
+            }]
+        );
+        assert!(recoveries(&rr).is_empty());
+
+        rr.on_success(Token(0));
+        assert!(recoveries(&rr).is_empty());
+    }
+
+    #[test]
+    fn rr_recommend_weights() {
+        let rr = RoundRobin::new(&[1, 1, 1, 1]);
+        assert!(rr.recommend_weights().iter().all(|(_, w)| *w == 0));
+
+        for (i, n) in [100, 50, 25, 0].into_iter().enumerate() {
+            for _ in 0..n {
+                rr.on_success(Token(i as u8));
+            }
+        }
+        let weights = |rr: &RoundRobin| -> Vec<u8> { rr.recommend_weights().into_iter().map(|x| x.1).collect() };
+        assert_eq!(weights(&rr), vec![100, 50, 25, 0]);
+
+        // as many successes twice as fast
+        rr.on_latency(Token(0), 2000);
+        rr.on_latency(Token(1), 1000);
+        rr.on_latency(Token(2), 2000);
+        assert_eq!(weights(&rr), vec![100, 100, 25, 0]);
+        assert_eq!(rr.weights(), vec![1; 4]);
+    }
+
+    #[test]
+    fn rr_max_cycle_length() {
+        let weights = [255, 254, 253, 1];
+        assert_eq!(RoundRobin::new(&weights).cycle_length(), 763);
+
+        let rr = RoundRobin::new(&weights).with_max_cycle_length(100);
+        let len = rr.cycle_length();
+        assert!(len <= 100, "{}", len);
+
+        // roughly the same shares, the configured weights kept
+        assert_eq!(rr.weights(), weights);
+        let targets = |rr: &RoundRobin| -> Vec<u8> { rr.snapshot().nodes.iter().map(|x| x.target_weight).collect() };
+        let scaled = targets(&rr);
+        let sum: u32 = scaled.iter().map(|w| *w as u32).sum();
+        for (w, x) in weights.iter().zip(&scaled) {
+            let share = *w as f64 / 763.0;
+            assert!((share - *x as f64 / sum as f64).abs() < 0.01, "{:?}", scaled);
+        }
+        assert_eq!(scaled[3], 1);
+
+        // new weights are fitted too
+        rr.set_weights(&[200, 101, 50, 50]);
+        assert_eq!(rr.weights(), vec![200, 101, 50, 50]);
+        assert_eq!(targets(&rr), vec![50, 25, 12, 12]);
+        rr.set_weights(&[2, 1, 1, 1]);
+        assert_eq!(targets(&rr), vec![2, 1, 1, 1]);
+
+        // scaled at runtime from the fitted weight
+        rr.set_weights(&[200, 101, 50, 50]);
+        rr.scale_weight(Token(0), 0.5);
+        assert_eq!(targets(&rr), vec![25, 25, 12, 12]);
+
+        // no limit by default
+        assert_eq!(RoundRobin::new(&weights).with_max_cycle_length(0).weights(), weights);
+    }
+
+    #[test]
+    fn rr_enable_disable() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 3,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[3, 1], health).with_clock(clock.clone());
+        rr.on_failure(Token(0));
+        rr.disable(Token(0));
+        assert!(!rr.is_enabled(Token(0)));
+        assert!((0..100).all(|_| rr.next(&()) == Some(Token(1))));
+
+        // nothing lost meanwhile
+        clock.advance(1000);
+        rr.enable(Token(0));
+        assert!(rr.is_enabled(Token(0)));
+        assert_eq!(rr.weights(), vec![3, 1]);
+        assert_eq!(rr.snapshot().nodes[0].fails, 1);
+        let mut distro = [0; 2];
+        for _ in 0..400 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [300, 100]);
+
+        // the only peer
+        let rr = RoundRobin::new(&[1]);
+        rr.disable(Token(0));
+        assert_eq!(rr.next(&()), None);
+        rr.enable(Token(0));
+        assert_eq!(rr.next(&()), Some(Token(0)));
+    }
+
+    #[test]
+    fn rr_fork() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[3, 2, 1], health).with_clock(clock.clone());
+        let twin = RoundRobin::with_health(&[3, 2, 1], health).with_clock(clock.clone());
+        for _ in 0..4 {
+            assert_eq!(rr.next(&()), twin.next(&()));
+        }
+
+        // same schedule from here
+        let fork = rr.fork();
+        let token = rr.next(&());
+        assert_eq!(fork.next(&()), token);
+        assert_eq!(twin.next(&()), token);
+
+        // what if the second peer dies
+        fork.on_failure(Token(1));
+        fork.set_weights(&[1, 1, 5]);
+        assert!(!fork.is_healthy(Token(1)));
+        assert!((0..60).all(|_| fork.next(&()) != Some(Token(1))));
+
+        // the original is untouched
+        assert!(rr.is_healthy(Token(1)));
+        assert_eq!(rr.weights(), vec![3, 2, 1]);
+        assert_eq!(rr.total_active_conns(), 5);
+        for _ in 0..60 {
+            assert_eq!(rr.next(&()), twin.next(&()));
+        }
+    }
+
+    #[test]
+    fn rr_set_weight_mid_ramp() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[20, 20], HealthConfig::default()).with_clock(clock.clone());
+        rr.on_failure(Token(0));
+        clock.advance(11);
+        rr.on_success(Token(0));
+        for _ in 0..12 {
+            rr.next(&());
+        }
+        let ew = rr.effective_weights()[0];
+        assert!(ew > 3 && ew < 20, "{}", ew);
+
+        // lowered below ew, clamped at once
+        rr.set_weight(Token(0), 3);
+        assert_eq!(rr.weights(), vec![3, 20]);
+        assert_eq!(rr.effective_weights(), vec![3, 20]);
+        let mut distro = [0; 2];
+        for _ in 0..230 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [30, 200]);
+
+        // raised, ramps up
+        rr.set_weight(Token(0), 20);
+        assert_eq!(rr.effective_weights(), vec![3, 20]);
+        rr.next(&());
+        assert_eq!(rr.effective_weights(), vec![4, 20]);
+
+        // unknown peer
+        rr.set_weight(Token(2), 1);
+        assert_eq!(rr.weights(), vec![20, 20]);
+    }
+
+    #[test]
+    fn rr_generation() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let rr = RoundRobin::from_specs(&[NodeSpec::new(a, 1), NodeSpec::new(b, 1)], None);
+        assert_eq!(rr.generation(), 0);
+
+        let (token, generation) = rr.next_with_generation(&()).unwrap();
+        assert_eq!(token, Token(0));
+        assert_eq!(rr.next_with_generation(&()), Some((Token(1), 0)));
+
+        // the first peer is removed, its token now names the other one
+        rr.swap_topology(&[NodeSpec::new(b, 1)]);
+        assert_ne!(rr.generation(), generation);
+        assert_eq!(rr.addr(token), Some(b));
+
+        rr.set_weights(&[2]);
+        assert_eq!(rr.next_with_generation(&()), Some((Token(0), 2)));
+        assert_eq!(rr.fork().generation(), 2);
+    }
+
+    #[test]
+    fn rr_duplicate_policy() {
+        let a: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let specs = [
+            NodeSpec::new(a, 2),
+            NodeSpec::new(b, 1),
+            NodeSpec {
+                max_conns: 10,
+                ..NodeSpec::new(a, 3)
+            },
+        ];
+
+        let rr = RoundRobin::from_specs(&specs, None);
+        assert_eq!(rr.total(), 3);
+        assert_eq!(rr.weights(), vec![2, 1, 3]);
+        assert_eq!(rr.addr(Token(2)), Some(a));
+
+        let rr = RoundRobin::from_specs_with(&specs, None, DuplicatePolicy::Merge);
+        assert_eq!(rr.total(), 2);
+        assert_eq!(rr.weights(), vec![5, 1]);
+        assert_eq!((rr.addr(Token(0)), rr.addr(Token(1))), (Some(a), Some(b)));
+
+        // clamped
+        let specs = [NodeSpec::new(a, 200), NodeSpec::new(a, 100)];
+        let rr = RoundRobin::from_specs_with(&specs, None, DuplicatePolicy::Merge);
+        assert_eq!(rr.weights(), vec![255]);
+    }
+
+    #[test]
+    fn rr_labels() {
+        let label = |k: &str, v: &str| (k.to_string(), v.to_string());
+        let spec = |i: u8, labels| NodeSpec {
+            labels,
+            ..NodeSpec::new(SocketAddr::from(([10, 0, 0, i], 80)), 1)
+        };
+
+        let rr = RoundRobin::from_specs(
+            &[spec(1, vec![label("zone", "a"), label("tier", "hot")]), spec(2, vec![])],
+            None,
+        );
+        let snapshot = rr.snapshot();
+        assert_eq!(snapshot.nodes[0].labels, vec![label("zone", "a"), label("tier", "hot")]);
+        assert!(snapshot.nodes[1].labels.is_empty());
+
+        // follow the specs
+        rr.swap_topology(&[spec(2, vec![label("zone", "b")])]);
+        assert_eq!(rr.snapshot().nodes[0].labels, vec![label("zone", "b")]);
+
+        assert!(RoundRobin::new(&[1]).snapshot().nodes[0].labels.is_empty());
+    }
+
+    #[test]
+    fn rr_next_topk() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            ..Default::default()
+        };
+        let weights: Vec<u8> = (1..=10).collect();
+        let rr = RoundRobin::with_health(&weights, health).with_clock(clock.clone());
+
+        let mut distro = [0; 10];
+        for _ in 0..270 {
+            distro[rr.next_topk(&(), 3).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [0, 0, 0, 0, 0, 0, 0, 80, 90, 100]);
+
+        // an ejected member is replaced at once
+        rr.on_failure(Token(9));
+        let mut distro = [0; 10];
+        for _ in 0..240 {
+            distro[rr.next_topk(&(), 3).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [0, 0, 0, 0, 0, 0, 70, 80, 90, 0]);
+
+        assert_eq!(rr.next_topk(&(), 0), None);
+    }
+
+    #[test]
+    fn rr_try_next_now() {
+        use std::time::Instant;
+
+        let rr = RoundRobin::new(&[1, 1, 1]);
+        let guard = rr.nodes.lock().unwrap();
+        let start = Instant::now();
+        // nothing selected yet
+        assert_eq!(
+            std::thread::scope(|s| s.spawn(|| rr.try_next_now(&())).join().unwrap()),
+            None
+        );
+        drop(guard);
+
+        assert_eq!(rr.try_next_now(&()), Some(Token(0)));
+        assert_eq!(rr.try_next_now(&()), Some(Token(1)));
+
+        // the last one again, without waiting
+        let guard = rr.nodes.lock().unwrap();
+        let picks: Vec<_> = std::thread::scope(|s| {
+            s.spawn(|| (0..3).map(|_| rr.try_next_now(&())).collect())
+                .join()
+                .unwrap()
+        });
+        assert_eq!(picks, vec![Some(Token(1)); 3]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        drop(guard);
+        assert_eq!(rr.active_conns(Token(1)), 4);
+        assert_eq!(rr.try_next_now(&()), Some(Token(2)));
+    }
+
+    #[test]
+    fn rr_try_next_now_unavailable() {
+        let health = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1], health);
+        let contended = |rr: &RoundRobin| {
+            let guard = rr.nodes.lock().unwrap();
+            let token = std::thread::scope(|s| s.spawn(|| rr.try_next_now(&())).join().unwrap());
+            drop(guard);
+            token
+        };
+        let reselect = |rr: &RoundRobin| {
+            let token = rr.next(&()).unwrap();
+            assert_eq!(contended(rr), Some(token));
+            rr.on_disconnect(token);
+            rr.on_disconnect(token);
+            token
+        };
+
+        let token = reselect(&rr);
+        rr.drain(token);
+        assert_eq!(contended(&rr), None);
+        rr.undrain(token);
+
+        let token = reselect(&rr);
+        rr.on_failure(token);
+        assert_eq!(contended(&rr), None);
+
+        let token = reselect(&rr);
+        rr.report_unhealthy(token, 10);
+        assert_eq!(contended(&rr), None);
+
+        let rr = RoundRobin::new(&[1, 1]);
+        let token = reselect(&rr);
+        rr.set_max_conns(token, 5);
+        assert_eq!(contended(&rr), None);
+        // nor is a capped pick remembered
+        rr.next(&());
+        rr.next(&());
+        assert_eq!(contended(&rr), None);
+
+        let rr = RoundRobin::new(&[1, 1]);
+        reselect(&rr);
+        rr.drain_all();
+        assert_eq!(contended(&rr), None);
+    }
+
+    #[cfg(feature = "lock-time")]
+    #[test]
+    fn rr_select_lock_time() {
+        let rr = RoundRobin::new(&[1; 255]);
+        assert_eq!(rr.snapshot().select_lock_p99_micros, None);
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..500 {
+                        rr.next(&());
+                    }
+                });
+            }
+        });
+
+        // the scan of 255 peers, never the wait for the lock
+        let p99 = rr.snapshot().select_lock_p99_micros.unwrap();
+        assert!(p99 > 0 && p99 < 100_000, "{}", p99);
+        assert!(rr.select_lock_micros(0.5).unwrap() <= p99);
+    }
+
+    #[test]
+    fn rr_rounding() {
+        let scaled = |rounding| {
+            let rr = RoundRobin::new(&[1, 1]).with_rounding(rounding);
+            rr.scale_weight(Token(0), 0.4);
+            let mut distro = [0; 2];
+            for _ in 0..10 {
+                distro[rr.next(&()).unwrap().0 as usize] += 1;
+            }
+            (rr.effective_weights()[0], distro)
+        };
+        assert_eq!(scaled(RoundingPolicy::Floor), (0, [0, 10]));
+        assert_eq!(scaled(RoundingPolicy::Round), (0, [0, 10]));
+        assert_eq!(scaled(RoundingPolicy::Ceil), (1, [5, 5]));
+
+        // exact products are not rounded up
+        let rr = RoundRobin::new(&[10]).with_rounding(RoundingPolicy::Ceil);
+        let target = |rr: &RoundRobin| rr.snapshot().nodes[0].target_weight;
+        rr.scale_weight(Token(0), 0.3);
+        assert_eq!(target(&rr), 3);
+        rr.scale_weight(Token(0), 0.35);
+        assert_eq!(target(&rr), 4);
+
+        // 9 * 165 / 255 = 5.8
+        let loaded = |rounding| {
+            let rr = RoundRobin::new(&[9, 1]).with_rounding(rounding);
+            rr.report_load(Token(0), 90);
+            rr.effective_weights()[0]
+        };
+        assert_eq!(loaded(RoundingPolicy::Floor), 5);
+        assert_eq!(loaded(RoundingPolicy::Round), 6);
+    }
+
+    #[test]
+    fn rr_probation() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let policy = ProbationPolicy {
+            threshold: 0.3,
+            interval_secs: 10,
+        };
+        let rr = RoundRobin::new(&[5, 5, 5])
+            .with_probation(policy)
+            .with_clock(clock.clone());
+        let distro = |n| {
+            let mut distro = [0; 3];
+            for _ in 0..n {
+                distro[rr.next(&()).unwrap().0 as usize] += 1;
+            }
+            distro
+        };
+
+        // reviewed with nothing to go on
+        assert_eq!(distro(15), [5, 5, 5]);
+
+        // the last peer fails half of the time, and is a bit slower
+        for i in 0..20 {
+            rr.on_success(Token(0));
+            rr.on_success(Token(1));
+            rr.on_latency(Token(0), 1000);
+            rr.on_latency(Token(1), 1000);
+            rr.on_latency(Token(2), 1100);
+            match i % 2 {
+                0 => rr.on_success(Token(2)),
+                _ => rr.on_failure(Token(2)),
+            }
+        }
+        assert_eq!(distro(15), [5, 5, 5]);
+        assert!(!rr.is_on_probation(Token(2)));
+
+        clock.advance(10);
+        assert_eq!(distro(11), [5, 5, 1]);
+        assert!(rr.is_on_probation(Token(2)));
+        assert_eq!(rr.weights(), vec![5, 5, 5]);
+
+        // still bad, no earlier than the interval
+        rr.on_failure(Token(2));
+        clock.advance(5);
+        assert!(rr.is_on_probation(Token(2)));
+
+        // improved
+        for _ in 0..20 {
+            rr.on_success(Token(2));
+        }
+        assert_eq!(distro(11), [5, 5, 1]);
+        clock.advance(5);
+        distro(1);
+        assert!(!rr.is_on_probation(Token(2)));
+        distro(30);
+        assert_eq!(distro(15), [5, 5, 5]);
+    }
+
+    #[test]
+    fn rr_audit_log() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1], health)
+            .with_audit_log(16)
+            .with_clock(clock.clone());
+
+        rr.on_failure(Token(0));
+        clock.advance(11);
+        rr.on_success(Token(0));
+        // not a change
+        rr.on_success(Token(0));
+        clock.advance(1);
+        rr.set_weight(Token(1), 3);
+        rr.set_weight(Token(1), 3);
+        rr.disable(Token(0));
+        clock.advance(1);
+        rr.enable(Token(0));
+        rr.drain(Token(1));
+        rr.undrain(Token(1));
+
+        let log: Vec<(u32, Option<u8>, AuditEvent)> = rr
+            .audit_log()
+            .iter()
+            .map(|x| (x.at, x.token.map(|t| t.0), x.event))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (100, Some(0), AuditEvent::Ejected(EjectionReason::Failures(None))),
+                (111, Some(0), AuditEvent::Recovered),
+                (112, Some(1), AuditEvent::WeightChanged { from: 1, to: 3 }),
+                (112, Some(0), AuditEvent::Disabled),
+                (113, Some(0), AuditEvent::Enabled),
+                (113, Some(1), AuditEvent::Drained),
+                (113, Some(1), AuditEvent::Undrained),
+            ]
+        );
+
+        // bounded
+        let rr = RoundRobin::new(&[1]).with_audit_log(2);
+        for _ in 0..3 {
+            rr.drain(Token(0));
+        }
+        assert_eq!(rr.audit_log().len(), 2);
+        assert!(RoundRobin::new(&[1]).audit_log().is_empty());
+    }
+
+    #[test]
+    fn rr_parked() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            max_consecutive_ejections: 3,
+            parked_secs: 300,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1], health)
+            .with_audit_log(16)
+            .with_clock(clock.clone());
+
+        // flap: back in, out again right away
+        for _ in 0..2 {
+            rr.on_failure(Token(0));
+            assert_eq!(rr.ejected_until(Token(0)), Some(clock.now() + 10));
+            clock.advance(11);
+            rr.on_success(Token(0));
+            assert!(!rr.is_parked(Token(0)));
+        }
+        rr.on_failure(Token(0));
+        assert!(rr.is_parked(Token(0)));
+        assert_eq!(rr.ejected_until(Token(0)), Some(clock.now() + 300));
+        let parked = rr.audit_log().iter().filter(|x| x.event == AuditEvent::Parked).count();
+        assert_eq!(parked, 1);
+
+        // out past the usual timeout
+        clock.advance(11);
+        assert!(!rr.is_healthy(Token(0)));
+        assert!((0..10).all(|_| rr.next(&()) == Some(Token(1))));
+
+        clock.advance(290);
+        rr.on_success(Token(0));
+        assert!(!rr.is_parked(Token(0)));
+        assert!(rr.is_healthy(Token(0)));
+
+        // staying up forgives past ejections
+        clock.advance(10);
+        rr.on_success(Token(0));
+        rr.on_failure(Token(0));
+        assert!(!rr.is_parked(Token(0)));
+        assert_eq!(rr.ejected_until(Token(0)), Some(clock.now() + 10));
+    }
+
+    #[test]
+    fn rr_reserve() {
+        let rr = RoundRobin::new(&[1, 1]);
+        rr.set_max_conns(Token(0), 10);
+
+        rr.reserve(Token(0), 8);
+        assert_eq!(rr.reserved_conns(Token(0)), 8);
+        let tokens: Vec<Token> = (0..10).map(|_| rr.next(&()).unwrap()).collect();
+        assert_eq!(tokens.iter().filter(|x| **x == Token(0)).count(), 2);
+        assert_eq!(rr.active_conns(Token(0)), 2);
+        assert!((0..10).all(|_| rr.next(&()) == Some(Token(1))));
+
+        rr.release_reservation(Token(0), 8);
+        assert_eq!(rr.reserved_conns(Token(0)), 0);
+        assert!((0..10).any(|_| rr.next(&()) == Some(Token(0))));
+
+        // unknown peer, over-release
+        rr.reserve(Token(2), 1);
+        assert_eq!(rr.reserved_conns(Token(2)), 0);
+        rr.release_reservation(Token(0), 1);
+        assert_eq!(rr.reserved_conns(Token(0)), 0);
+    }
+
+    #[test]
+    fn rr_next_masked() {
+        let rr = RoundRobin::new(&[1, 1, 1]);
+        let twin = RoundRobin::new(&[1, 1, 1]);
+
+        let token = twin.next(&()).unwrap();
+        let masked = rr.next_masked(&(), 1 << token.0).unwrap();
+        assert_ne!(masked, token);
+
+        // only the rest are spread
+        let mut distro = [0u32; 3];
+        for _ in 0..30 {
+            distro[rr.next_masked(&(), 0b010).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro[1], 0);
+        assert_eq!(distro[0] + distro[2], 30);
+        assert!(distro[0].abs_diff(distro[2]) <= 1);
+
+        assert_eq!(rr.next_masked(&(), 0b111), None);
+
+        let rr = RoundRobin::new(&[1]);
+        assert_eq!(rr.next_masked(&(), 0b1), None);
+        assert_eq!(rr.next_masked(&(), 0b10), Some(Token(0)));
+    }
+
+    #[test]
+    fn rr_degraded_for_secs() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let health = HealthConfig {
+            max_fails: 1,
+            fail_timeout_secs: 10,
+            ..Default::default()
+        };
+        let rr = RoundRobin::with_health(&[1, 1, 1], health).with_clock(clock.clone());
+        assert_eq!(rr.degraded_for_secs(), 0);
+
+        rr.on_failure(Token(0));
+        clock.advance(5);
+        assert_eq!(rr.degraded_for_secs(), 5);
+        // overlapping, still the same period
+        rr.on_failure(Token(1));
+        clock.advance(8);
+        assert_eq!(rr.degraded_for_secs(), 13);
+
+        // both timed out
+        clock.advance(3);
+        assert_eq!(rr.degraded_for_secs(), 0);
+
+        rr.report_unhealthy(Token(2), 20);
+        clock.advance(4);
+        assert_eq!(rr.degraded_for_secs(), 4);
+        clock.advance(16);
+        assert_eq!(rr.degraded_for_secs(), 0);
+    }
+
+    #[test]
+    fn rr_min_share() {
+        fn share(rr: &RoundRobin, n: usize) -> usize {
+            (0..n).filter(|_| rr.next(&()) == Some(Token(0))).count()
+        }
+
+        // 1 in 256 by weight
+        assert!(share(&RoundRobin::new(&[1, 255]), 1000) < 10);
+
+        let rr = RoundRobin::new(&[1, 255]).with_min_share(0.1);
+        assert!(share(&rr, 1000) >= 100);
+        // spread, not in a burst
+        assert!(share(&rr, 20) >= 2);
+
+        // past 1 / peers, plain round robin
+        let rr = RoundRobin::new(&[1, 255]).with_min_share(0.5);
+        assert_eq!(share(&rr, 1000), 500);
+
+        // only live peers
+        let rr = RoundRobin::new(&[1, 255, 1]).with_min_share(0.1);
+        rr.disable(Token(2));
+        assert!((0..1000).all(|_| rr.next(&()) != Some(Token(2))));
+    }
+
+    #[test]
+    fn rr_weight_scaled_timeout() {
+        use crate::clock::MockClock;
+
+        let config = HealthConfig {
+            fail_timeout_secs: 30,
+            weight_scaled_timeout: true,
+            ..Default::default()
+        };
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::with_health(&[10, 1], config).with_clock(clock.clone());
+        assert_eq!(rr.ejected_until(Token(0)), None);
+
+        rr.on_failure(Token(0));
+        rr.on_failure(Token(1));
+        let heavy = rr.ejected_until(Token(0)).unwrap();
+        let light = rr.ejected_until(Token(1)).unwrap();
+        assert_eq!(heavy, 103);
+        assert_eq!(light, 130);
+
+        // the heavy peer is probed back first
+        clock.advance(4);
+        assert_eq!(rr.half_open(), vec![Token(0)]);
+        assert_eq!(rr.next(&()), Some(Token(0)));
+
+        // flat by default
+        let rr = RoundRobin::with_health(&[10, 1], HealthConfig::default()).with_clock(clock);
+        rr.on_failure(Token(0));
+        rr.on_failure(Token(1));
+        assert_eq!(rr.ejected_until(Token(0)), rr.ejected_until(Token(1)));
+    }
+
+    #[test]
+    fn rr_snapshot_into() {
+        let rr = RoundRobin::with_health(&[1, 2, 3], HealthConfig::default());
+        rr.on_failure(Token(1));
+
+        let mut buf = Vec::new();
+        rr.snapshot_into(&mut buf);
+        assert_eq!(buf, rr.snapshot().nodes);
+        let (ptr, cap) = (buf.as_ptr(), buf.capacity());
+
+        for _ in 0..10 {
+            rr.next(&());
+            rr.snapshot_into(&mut buf);
+            assert_eq!(buf.len(), 3);
+            assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, cap));
+            assert_eq!(buf, rr.snapshot().nodes);
+        }
+        assert_eq!(buf[1].state, NodeState::Ejected);
+
+        // from the published snapshot too
+        let rr = RoundRobin::new(&[1, 2, 3]).with_snapshot_interval(5);
+        rr.snapshot_into(&mut buf);
+        assert_eq!((buf.as_ptr(), buf.capacity()), (ptr, cap));
+        assert_eq!(buf, rr.snapshot().nodes);
+    }
+
+    #[test]
+    fn rr_evacuate() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let rr = RoundRobin::new(&[120, 120, 120]).with_clock(clock.clone());
+        rr.evacuate(Token(0), 10);
+
+        let mut last = [0.0, 0.0, 0.0];
+        for t in 0..=10 {
+            let mut distro = [0.0; 3];
+            for _ in 0..3600 {
+                distro[rr.next(&()).unwrap().0 as usize] += 1.0 / 3600.0;
+            }
+
+            let left = 120.0 * (10 - t) as f64 / 10.0;
+            let expect = left / (left + 240.0);
+            println!("{}: {:?}", t, distro);
+            assert!((distro[0] - expect).abs() < 0.02);
+            if t > 0 {
+                assert!(distro[0] < last[0]);
+                assert!(distro[1] > last[1] && distro[2] > last[2]);
+            }
+            last = distro;
+            clock.advance(1);
+        }
+
+        // then drained
+        assert!((0..100).all(|_| rr.next(&()) != Some(Token(0))));
+        assert!(!rr.is_all_draining());
+
+        rr.undrain(Token(0));
+        assert!((0..100).any(|_| rr.next(&()) == Some(Token(0))));
+    }
+
+    #[test]
+    fn rr_set_weights_concurrent() {
+        use std::sync::atomic::AtomicBool;
+        use std::time::Instant;
+
+        // selections interleave with reweights, and never see a torn update
+        let rr = RoundRobin::new(&[1, 1, 1]);
+        let done = AtomicBool::new(false);
+        let stall = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut stall = Duration::ZERO;
+                        while !done.load(Ordering::Relaxed) {
+                            let start = Instant::now();
+                            let token = rr.next(&()).unwrap();
+                            stall = stall.max(start.elapsed());
+                            assert!(token.0 < 3);
+                            let weights = rr.weights();
+                            assert!(weights == [1, 1, 1] || weights == [3, 2, 1] || weights == [1, 0, 4]);
+                        }
+                        stall
+                    })
+                })
+                .collect();
+
+            for i in 0..2000 {
+                match i % 2 {
+                    0 => rr.set_weights(&[3, 2, 1]),
+                    _ => rr.set_weights(&[1, 0, 4]),
+                }
+            }
+            rr.set_weights(&[3, 2, 1]);
+            done.store(true, Ordering::Relaxed);
+            workers.into_iter().map(|x| x.join().unwrap()).max().unwrap()
+        });
+        println!("max stall: {:?}", stall);
+        assert!(stall < Duration::from_secs(1));
+        assert_eq!(rr.weights(), vec![3, 2, 1]);
+
+        // ramped up, then exact cycles
+        for _ in 0..10 {
+            rr.next(&());
+        }
+        let mut distro = [0; 3];
+        for _ in 0..600 {
+            distro[rr.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [300, 200, 100]);
+
+        // extra weights ignored, missing ones unchanged
+        rr.set_weights(&[1, 1, 1, 9]);
+        rr.set_weights(&[2]);
+        assert_eq!(rr.weights(), vec![2, 1, 1]);
     }
 }
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+use crate::Token;
+
+/// Which selections to log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogSampling {
+    #[default]
+    Off,
+    /// Log one in every N selections.
+    OneIn(u64),
+    /// Log a selection only if it differs from the previous one.
+    OnChange,
+}
+
+/// Selection log sampler.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    mode: LogSampling,
+    count: AtomicU64,
+    // u8::MAX + 1 means none
+    last: AtomicU16,
+}
+
+impl Sampler {
+    /// Constructor.
+    pub fn new(mode: LogSampling) -> Self {
+        Self {
+            mode,
+            count: AtomicU64::new(0),
+            last: AtomicU16::new(u8::MAX as u16 + 1),
+        }
+    }
+
+    /// Whether this selection should be logged.
+    pub fn hit(&self, token: Token) -> bool {
+        match self.mode {
+            LogSampling::Off => false,
+            LogSampling::OneIn(0) => false,
+            LogSampling::OneIn(n) => self.count.fetch_add(1, Ordering::Relaxed).is_multiple_of(n),
+            LogSampling::OnChange => self.last.swap(token.0 as u16, Ordering::Relaxed) != token.0 as u16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_one_in() {
+        let sampler = Sampler::new(LogSampling::OneIn(10));
+        let logged = (0..10_000).filter(|i| sampler.hit(Token(*i as u8))).count();
+        assert_eq!(logged, 1_000);
+
+        let sampler = Sampler::new(LogSampling::Off);
+        assert!((0..100).all(|_| !sampler.hit(Token(0))));
+    }
+
+    #[test]
+    fn sample_on_change() {
+        let sampler = Sampler::new(LogSampling::OnChange);
+        let tokens = [0, 0, 1, 1, 1, 0, 2, 2];
+        let logged: Vec<bool> = tokens.iter().map(|x| sampler.hit(Token(*x))).collect();
+        assert_eq!(logged, vec![true, false, true, false, false, true, true, false]);
+    }
+}
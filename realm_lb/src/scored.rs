@@ -0,0 +1,219 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use super::{Balance, Strategy, Token};
+use crate::clock::Clock;
+use crate::error::BalanceError;
+use crate::failure::FailureClass;
+use crate::health::HealthConfig;
+use crate::round_robin::RoundRobin;
+
+/// Live signals of a peer, scored by [`ScoredBalancer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeSignals {
+    pub token: Token,
+    pub weight: u8,
+    pub active_conns: u32,
+    /// Moving average of response time, None until the first report.
+    pub latency_micros: Option<f64>,
+    /// Moving average of failures, from 0.0 (none) to 1.0 (all).
+    pub error_rate: f64,
+}
+
+/// Scoring function, the lowest score is selected.
+pub type Scorer = Arc<dyn Fn(&NodeSignals) -> f64 + Send + Sync>;
+
+/// Custom score balancer.
+///
+/// Select the available peer with the lowest score of its signals, peers
+/// with a zero weight or a NaN score are skipped. Without a scorer, the
+/// score is `(conns + 1) / weight`, the same as least connections.
+///
+/// Signals, connections and health are tracked by a [`RoundRobin`],
+/// so ejected, saturated, drained or disabled peers are never selected.
+pub struct ScoredBalancer {
+    rr: RoundRobin,
+    scorer: Scorer,
+}
+
+impl Debug for ScoredBalancer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScoredBalancer").field("rr", &self.rr).finish()
+    }
+}
+
+impl Balance for ScoredBalancer {
+    type State = ();
+
+    fn total(&self) -> u8 {
+        self.rr.total()
+    }
+
+    fn strategy_name(&self) -> &'static str {
+        Strategy::Scored.name()
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        Self::with_scorer(
+            weights,
+            Arc::new(|x: &NodeSignals| (x.active_conns as f64 + 1.0) / x.weight as f64),
+        )
+    }
+
+    fn next(&self, _: &Self::State) -> Option<Token> {
+        self.rr.select_scored(&*self.scorer)
+    }
+
+    fn on_disconnect(&self, token: Token) {
+        self.rr.on_disconnect(token);
+    }
+
+    fn on_success(&self, token: Token) {
+        self.rr.on_success(token);
+    }
+
+    fn on_failure(&self, token: Token) {
+        self.rr.on_failure(token);
+    }
+
+    fn on_failure_classified(&self, token: Token, class: FailureClass) {
+        self.rr.on_failure_classified(token, class);
+    }
+
+    fn on_latency(&self, token: Token, micros: u64) {
+        self.rr.on_latency(token, micros);
+    }
+
+    fn on_connect_time(&self, token: Token, micros: u64) {
+        self.rr.on_connect_time(token, micros);
+    }
+}
+
+impl ScoredBalancer {
+    /// Constructor with a custom scorer.
+    pub fn with_scorer(weights: &[u8], scorer: Scorer) -> Self {
+        Self {
+            rr: RoundRobin::new(weights).with_error_tracking(),
+            scorer,
+        }
+    }
+
+    /// Skip ejected peers, like [`RoundRobin::with_health`].
+    ///
+    /// # Panics
+    ///
+    /// If `health` is invalid, see [`HealthConfig::validate`].
+    pub fn with_health(self, health: HealthConfig) -> Self {
+        if let Err(e) = health.validate() {
+            panic!("{}", e);
+        }
+        Self {
+            rr: self.rr.with_health_config(health),
+            ..self
+        }
+    }
+
+    /// Same as [`with_health`](Self::with_health), fail if the config is invalid.
+    pub fn try_with_health(self, health: HealthConfig) -> Result<Self, BalanceError> {
+        health.validate()?;
+        Ok(self.with_health(health))
+    }
+
+    /// Use another time source for the health check.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            rr: self.rr.with_clock(clock),
+            ..self
+        }
+    }
+
+    /// Get the passive health check config.
+    pub fn health_config(&self) -> Option<HealthConfig> {
+        self.rr.health_config()
+    }
+
+    /// Whether a peer could be selected right now.
+    pub fn is_healthy(&self, token: Token) -> bool {
+        self.rr.is_healthy(token)
+    }
+
+    /// Get signals of all peers.
+    pub fn signals(&self) -> Vec<NodeSignals> {
+        self.rr.signals()
+    }
+
+    /// Get configured weights of all peers.
+    pub fn weights(&self) -> Vec<u8> {
+        self.rr.weights()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scored_custom() {
+        // latency in ms, plus 10ms per active connection
+        let scorer: Scorer = Arc::new(|x| {
+            x.latency_micros.unwrap_or(0.0) / 1000.0 + x.active_conns as f64 * 10.0 + x.error_rate * 1000.0
+        });
+        let sb = ScoredBalancer::with_scorer(&[1, 1, 1], scorer);
+        sb.on_latency(Token(0), 5_000);
+        sb.on_latency(Token(1), 25_000);
+        sb.on_latency(Token(2), 40_000);
+
+        // 5, 15, then 25 wins a tie as the first peer, 35 does not
+        let picks: Vec<Token> = (0..4).map(|_| sb.next(&()).unwrap()).collect();
+        assert_eq!(picks, vec![Token(0), Token(0), Token(0), Token(1)]);
+        assert_eq!(sb.signals()[0].active_conns, 3);
+
+        for token in picks {
+            sb.on_disconnect(token);
+        }
+
+        // failing, avoided despite being fast
+        sb.on_failure(Token(0));
+        assert!(sb.signals()[0].error_rate > 0.0);
+        assert_eq!(sb.next(&()), Some(Token(1)));
+
+        // NaN never wins
+        let sb = ScoredBalancer::with_scorer(&[1, 1], Arc::new(|x| if x.token.0 == 0 { f64::NAN } else { 1.0 }));
+        assert!((0..10).all(|_| sb.next(&()) == Some(Token(1))));
+    }
+
+    #[test]
+    fn scored_default() {
+        let sb = ScoredBalancer::new(&[2, 1, 0]);
+        let mut distro = [0; 3];
+        for _ in 0..300 {
+            distro[sb.next(&()).unwrap().0 as usize] += 1;
+        }
+        assert_eq!(distro, [200, 100, 0]);
+    }
+
+    #[test]
+    fn scored_health() {
+        use crate::clock::MockClock;
+
+        let clock = Arc::new(MockClock::new(100));
+        let sb = ScoredBalancer::with_scorer(&[1, 1], Arc::new(|x| x.latency_micros.unwrap_or(0.0)))
+            .with_health(HealthConfig::default())
+            .with_clock(clock.clone());
+        sb.on_latency(Token(0), 1_000);
+        sb.on_latency(Token(1), 9_000);
+        assert_eq!(sb.next(&()), Some(Token(0)));
+
+        // the best score, but ejected
+        sb.on_failure(Token(0));
+        assert!(!sb.is_healthy(Token(0)));
+        assert!((0..10).all(|_| sb.next(&()) == Some(Token(1))));
+
+        clock.advance(11);
+        assert_eq!(sb.next(&()), Some(Token(0)));
+
+        let sb = ScoredBalancer::new(&[1]).with_health(HealthConfig::default());
+        sb.on_failure(Token(0));
+        assert_eq!(sb.next(&()), None);
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Outcomes are counted over two windows of this length.
+const WINDOW_SECS: u32 = 10;
+/// Fewer outcomes than this never shed.
+const MIN_SAMPLES: u32 = 16;
+
+/// Pool level load shedding.
+///
+/// Once the pool-wide error rate of recent connections exceeds
+/// `pool_shed_error_rate`, `pool_shed_fraction` of the selections
+/// fail on purpose, to apply backpressure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShedConfig {
+    pub pool_shed_error_rate: f32,
+    pub pool_shed_fraction: f32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Window {
+    start: u32,
+    ok: u32,
+    fail: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Windows {
+    cur: Window,
+    prev: Window,
+}
+
+impl Windows {
+    fn rotate(&mut self, now: u32) {
+        let elapsed = now.saturating_sub(self.cur.start);
+        if elapsed < WINDOW_SECS {
+            return;
+        }
+
+        self.prev = if elapsed < 2 * WINDOW_SECS {
+            self.cur
+        } else {
+            Window::default()
+        };
+        self.cur = Window {
+            start: now,
+            ..Default::default()
+        };
+    }
+
+    fn error_rate(&self) -> Option<f32> {
+        let fail = self.cur.fail + self.prev.fail;
+        let total = fail + self.cur.ok + self.prev.ok;
+        (total >= MIN_SAMPLES).then(|| fail as f32 / total as f32)
+    }
+}
+
+/// Pool error rate tracker.
+#[derive(Debug, Default)]
+pub(crate) struct Shedder {
+    config: Option<ShedConfig>,
+    windows: Mutex<Windows>,
+    count: AtomicU64,
+}
+
+impl Shedder {
+    pub fn new(config: ShedConfig) -> Self {
+        Self {
+            config: Some(config),
+            ..Default::default()
+        }
+    }
+
+    /// An independent copy, with the same outcomes.
+    pub fn fork(&self) -> Self {
+        Self {
+            config: self.config,
+            windows: Mutex::new(*self.windows.lock().unwrap()),
+            count: AtomicU64::new(self.count.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn on_outcome(&self, now: u32, ok: bool) {
+        if self.config.is_none() {
+            return;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        windows.rotate(now);
+        if ok {
+            windows.cur.ok = windows.cur.ok.saturating_add(1);
+        } else {
+            windows.cur.fail = windows.cur.fail.saturating_add(1);
+        }
+    }
+
+    /// Whether the error rate is over the threshold.
+    pub fn is_active(&self, now: u32) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        windows.rotate(now);
+        windows.error_rate().is_some_and(|x| x > config.pool_shed_error_rate)
+    }
+
+    /// Whether to shed this selection, spread evenly like canary turns.
+    pub fn shed(&self, now: u32) -> bool {
+        if !self.is_active(now) {
+            return false;
+        }
+
+        let fraction = self.config.map_or(0.0, |x| x.pool_shed_fraction.clamp(0.0, 1.0)) as f64;
+        let n = self.count.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * fraction).floor() > (n * fraction).floor()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shed_windows() {
+        let shedder = Shedder::new(ShedConfig {
+            pool_shed_error_rate: 0.5,
+            pool_shed_fraction: 0.25,
+        });
+
+        // too few samples
+        for _ in 0..MIN_SAMPLES - 1 {
+            shedder.on_outcome(0, false);
+        }
+        assert!(!shedder.is_active(0));
+
+        shedder.on_outcome(0, false);
+        assert!(shedder.is_active(0));
+        assert_eq!((0..100).filter(|_| shedder.shed(0)).count(), 25);
+
+        // still counted in the previous window
+        assert!(shedder.is_active(WINDOW_SECS));
+        // then forgotten
+        assert!(!shedder.is_active(2 * WINDOW_SECS));
+
+        assert!(!Shedder::default().shed(0));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::Token;
+use crate::health::NodeState;
+
+/// Point-in-time view of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeStat {
+    pub token: Token,
+    /// Stable id from the spec.
+    pub key: Option<u64>,
+    /// Weight from the config.
+    pub configured_weight: u8,
+    /// Weight after runtime adjustment.
+    pub target_weight: u8,
+    /// Effective weight, ramps up toward the target.
+    pub ew: u8,
+    pub fails: u32,
+    pub conns: u32,
+    pub state: NodeState,
+    /// Moving average of connection setup time, None until the first report.
+    pub connect_ewma_micros: Option<u64>,
+    /// Metric labels from the spec.
+    pub labels: Vec<(String, String)>,
+}
+
+/// Point-in-time view of a balancer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BalanceSnapshot {
+    pub nodes: Vec<NodeStat>,
+    /// p99 of the time a selection held the peer list.
+    #[cfg(feature = "lock-time")]
+    pub select_lock_p99_micros: Option<u64>,
+}
+
+/// State change of a peer between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Transition {
+    Ejected,
+    Recovered,
+    WeightChanged { from: u8, to: u8 },
+}
+
+/// A changed peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeDelta {
+    pub token: Token,
+    pub transition: Transition,
+}
+
+impl BalanceSnapshot {
+    /// Compare with a prior snapshot, return changed peers.
+    /// Peers missing from either side are ignored.
+    pub fn diff(&self, prev: &BalanceSnapshot) -> Vec<NodeDelta> {
+        let mut deltas = Vec::new();
+
+        for cur in self.nodes.iter() {
+            let Some(old) = prev.nodes.iter().find(|x| x.token == cur.token) else {
+                continue;
+            };
+
+            match (old.state, cur.state) {
+                (NodeState::Healthy, NodeState::Ejected) => deltas.push(NodeDelta {
+                    token: cur.token,
+                    transition: Transition::Ejected,
+                }),
+                (NodeState::Ejected, NodeState::Healthy) => deltas.push(NodeDelta {
+                    token: cur.token,
+                    transition: Transition::Recovered,
+                }),
+                _ => {}
+            }
+
+            if old.configured_weight != cur.configured_weight {
+                deltas.push(NodeDelta {
+                    token: cur.token,
+                    transition: Transition::WeightChanged {
+                        from: old.configured_weight,
+                        to: cur.configured_weight,
+                    },
+                });
+            }
+        }
+
+        deltas
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{Balance, HealthConfig};
+    use crate::round_robin::RoundRobin;
+
+    #[test]
+    fn snapshot_serialize() {
+        let rr = RoundRobin::with_health(&[1, 2], HealthConfig::default());
+        rr.on_failure(Token(1));
+
+        let json = serde_json::to_value(rr.snapshot()).unwrap();
+        let node = &json["nodes"][1];
+        assert_eq!(node["token"], 1);
+        assert_eq!(node["configured_weight"], 2);
+        assert_eq!(node["target_weight"], 2);
+        assert_eq!(node["ew"], 0);
+        assert_eq!(node["fails"], 1);
+        assert_eq!(node["conns"], 0);
+        assert_eq!(node["state"], "ejected");
+        assert_eq!(json["nodes"][0]["state"], "healthy");
+    }
+}
@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+
+/// Peer specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSpec {
+    pub addr: SocketAddr,
+    pub weight: u8,
+    /// Free-form labels.
+    pub tags: Vec<String>,
+    /// Max active connections, 0 for unlimited.
+    pub max_conns: u32,
+    /// Tier, peers with a lower value are preferred
+    /// while any of them is available.
+    pub priority: u8,
+    /// Stable id, unlike a token kept across topology swaps.
+    /// Peers without one are told apart by address.
+    pub key: Option<u64>,
+    /// Metric labels, e.g. `("zone", "a")`, only read by snapshots.
+    pub labels: Vec<(String, String)>,
+}
+
+impl NodeSpec {
+    /// Peer without limits, in the first tier.
+    pub fn new(addr: SocketAddr, weight: u8) -> Self {
+        Self {
+            addr,
+            weight,
+            tags: Vec::new(),
+            max_conns: 0,
+            priority: 0,
+            key: None,
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// What to do with specs of the same address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DuplicatePolicy {
+    /// Distinct peers, e.g. to open more connections to the same backend.
+    #[default]
+    Keep,
+    /// One peer with the weights summed, the first spec for the rest.
+    /// Connection limits are summed too, unless one is unlimited.
+    Merge,
+}
+
+impl DuplicatePolicy {
+    /// Apply the policy, specs are kept in order of first appearance.
+    pub(crate) fn apply(&self, specs: &[NodeSpec]) -> Vec<NodeSpec> {
+        if *self == DuplicatePolicy::Keep {
+            return specs.to_vec();
+        }
+
+        let mut merged: Vec<NodeSpec> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            match merged.iter_mut().find(|x| x.addr == spec.addr) {
+                Some(x) => {
+                    if x.weight.checked_add(spec.weight).is_none() {
+                        log::warn!("[lb]merged weight of {} clamped to {}", x.addr, u8::MAX);
+                    }
+                    x.weight = x.weight.saturating_add(spec.weight);
+                    x.max_conns = match (x.max_conns, spec.max_conns) {
+                        (0, _) | (_, 0) => 0,
+                        (a, b) => a.saturating_add(b),
+                    };
+                }
+                None => merged.push(spec.clone()),
+            }
+        }
+        merged
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::future::{ready, Future};
+use std::sync::{Arc, Mutex};
+
+use super::{Balance, Token};
+use crate::clock::{Clock, SystemClock};
+
+/// Session key to peer map of [`Sticky`].
+///
+/// Both calls return a future, so that a store shared by
+/// several instances, e.g. over Redis, does not block.
+pub trait SessionStore: Send + Sync {
+    /// Get the peer a session is pinned to, if any.
+    fn get(&self, key: &str) -> impl Future<Output = Option<Token>> + Send;
+
+    /// Pin a session to a peer.
+    fn set(&self, key: &str, token: Token) -> impl Future<Output = ()> + Send;
+}
+
+/// In-process store, a session expires `ttl_secs` after it is pinned.
+#[derive(Debug)]
+pub struct MemoryStore {
+    // token, expires at
+    sessions: Mutex<HashMap<String, (Token, u32)>>,
+    ttl_secs: u32,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemoryStore {
+    /// Constructor.
+    pub fn new(ttl_secs: u32) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl_secs,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use another time source.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Get the number of sessions, expired ones included until looked up.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Whether there are no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup(&self, key: &str) -> Option<Token> {
+        let now = self.clock.now();
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(key) {
+            Some((token, expires)) if now < *expires => Some(*token),
+            Some(_) => {
+                sessions.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn pin(&self, key: &str, token: Token) {
+        let expires = self.clock.now().saturating_add(self.ttl_secs);
+        self.sessions.lock().unwrap().insert(key.to_string(), (token, expires));
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn get(&self, key: &str) -> impl Future<Output = Option<Token>> + Send {
+        ready(self.lookup(key))
+    }
+
+    fn set(&self, key: &str, token: Token) -> impl Future<Output = ()> + Send {
+        self.pin(key, token);
+        ready(())
+    }
+}
+
+/// Sticky sessions over another balancer.
+///
+/// A new session is selected by the inner balancer and pinned to
+/// the peer, later ones go to the same peer without asking it,
+/// so only new sessions count toward its connections.
+#[derive(Debug)]
+pub struct Sticky<B, S> {
+    inner: B,
+    store: S,
+}
+
+impl<B: Balance, S: SessionStore> Sticky<B, S> {
+    /// Constructor.
+    pub fn new(inner: B, store: S) -> Self {
+        Self { inner, store }
+    }
+
+    /// Get the peer of a session, select and pin one if there is none.
+    ///
+    /// A session pinned to a peer that no longer exists is selected again.
+    pub async fn next(&self, key: &str, state: &B::State) -> Option<Token> {
+        if let Some(token) = self.store.get(key).await.filter(|x| x.0 < self.inner.total()) {
+            return Some(token);
+        }
+
+        let token = self.inner.next(state)?;
+        self.store.set(key, token).await;
+        Some(token)
+    }
+
+    /// Get the inner balancer.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Get the session store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::round_robin::RoundRobin;
+    use std::task::{Context, Poll, Waker};
+
+    // stores here never wait
+    fn now_or_never<F: Future>(fut: F) -> F::Output {
+        let mut cx = Context::from_waker(Waker::noop());
+        match std::pin::pin!(fut).poll(&mut cx) {
+            Poll::Ready(x) => x,
+            Poll::Pending => panic!("pending"),
+        }
+    }
+
+    #[derive(Default)]
+    struct MockStore {
+        calls: Mutex<Vec<String>>,
+        pinned: Option<Token>,
+    }
+
+    impl SessionStore for MockStore {
+        fn get(&self, key: &str) -> impl Future<Output = Option<Token>> + Send {
+            self.calls.lock().unwrap().push(format!("get {}", key));
+            ready(self.pinned)
+        }
+
+        fn set(&self, key: &str, token: Token) -> impl Future<Output = ()> + Send {
+            self.calls.lock().unwrap().push(format!("set {} {}", key, token.0));
+            ready(())
+        }
+    }
+
+    #[test]
+    fn sticky_memory_store() {
+        let clock = Arc::new(MockClock::new(100));
+        let store = MemoryStore::new(60).with_clock(clock.clone());
+        let sticky = Sticky::new(RoundRobin::new(&[1, 1, 1]), store);
+
+        let a = now_or_never(sticky.next("a", &())).unwrap();
+        let b = now_or_never(sticky.next("b", &())).unwrap();
+        assert_ne!(a, b);
+        for _ in 0..10 {
+            assert_eq!(now_or_never(sticky.next("a", &())), Some(a));
+            assert_eq!(now_or_never(sticky.next("b", &())), Some(b));
+        }
+        assert_eq!(sticky.store().len(), 2);
+
+        // expired, then selected again
+        clock.advance(60);
+        assert_eq!(now_or_never(sticky.next("a", &())), Some(Token((b.0 + 1) % 3)));
+    }
+
+    #[test]
+    fn sticky_store_calls() {
+        let sticky = Sticky::new(RoundRobin::new(&[1, 1]), MockStore::default());
+        assert_eq!(now_or_never(sticky.next("a", &())), Some(Token(0)));
+        assert_eq!(*sticky.store().calls.lock().unwrap(), ["get a", "set a 0"]);
+
+        let store = MockStore {
+            pinned: Some(Token(1)),
+            ..Default::default()
+        };
+        let sticky = Sticky::new(RoundRobin::new(&[1, 1]), store);
+        assert_eq!(now_or_never(sticky.next("b", &())), Some(Token(1)));
+        assert_eq!(*sticky.store().calls.lock().unwrap(), ["get b"]);
+
+        // pinned to a peer that is gone
+        let store = MockStore {
+            pinned: Some(Token(5)),
+            ..Default::default()
+        };
+        let sticky = Sticky::new(RoundRobin::new(&[1, 1]), store);
+        assert_eq!(now_or_never(sticky.next("c", &())), Some(Token(0)));
+        assert_eq!(*sticky.store().calls.lock().unwrap(), ["get c", "set c 0"]);
+    }
+}
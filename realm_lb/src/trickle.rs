@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Probes of ejected peers while too few are healthy.
+#[derive(Debug, Default)]
+pub(crate) struct Trickle {
+    below: u8,
+    fraction: f32,
+    count: AtomicU64,
+}
+
+impl Trickle {
+    pub fn new(below: u8, fraction: f32) -> Self {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        Self {
+            below,
+            fraction,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// An independent copy, at the same turn.
+    pub fn fork(&self) -> Self {
+        Self {
+            count: AtomicU64::new(self.count.load(Ordering::Relaxed)),
+            ..*self
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.below != 0 && self.fraction > 0.0
+    }
+
+    /// Whether this selection probes an ejected peer, with `healthy` peers left.
+    ///
+    /// Turns are spread evenly like a canary's, and only
+    /// counted while below the threshold.
+    pub fn turn(&self, healthy: usize) -> bool {
+        if healthy >= self.below as usize {
+            return false;
+        }
+        spread_turn(&self.count, self.fraction)
+    }
+}
+
+/// Whether the call counted by `count` is a turn, for `fraction` of calls.
+///
+/// The n-th call is a turn if `floor(n * fraction)` steps up,
+/// which spreads the turns evenly.
+pub(crate) fn spread_turn(count: &AtomicU64, fraction: f32) -> bool {
+    let fraction = fraction as f64;
+    let n = count.fetch_add(1, Ordering::Relaxed) as f64;
+    ((n + 1.0) * fraction).floor() > (n * fraction).floor()
+}